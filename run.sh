@@ -2,4 +2,4 @@
 source .env
 cross build --target armv7-unknown-linux-gnueabihf && \
 rsync -avzP ./target/armv7-unknown-linux-gnueabihf/debug/smartmeter-exporter pi:/home/pi/smartmeter-exporter/ && \
-ssh pi "RUST_LOG=debug /home/pi/smartmeter-exporter/smartmeter-exporter"
\ No newline at end of file
+ssh pi "B_ID=$B_ID B_PW=$B_PW RUST_LOG=debug /home/pi/smartmeter-exporter/smartmeter-exporter"
\ No newline at end of file
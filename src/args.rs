@@ -0,0 +1,50 @@
+use clap::Parser;
+
+// Command line arguments for the frequently used settings, each of which falls back to the environment variable of
+// the same name. The values are kept raw, and parsed by the same functions as the other environment variables.
+// The B-route credentials are not accepted as arguments, which would be visible in /proc/<pid>/cmdline.
+#[derive(Debug, Parser)]
+#[command(about = "Prometheus exporter of the low-voltage smart meter over Wi-SUN B-route")]
+pub struct Args {
+    /// Serial port connected to the Wi-SUN module
+    #[arg(long, env = "SERIAL_PORT")]
+    pub serial_port: Option<String>,
+
+    /// Baud rate of the serial port
+    #[arg(long, env = "SERIAL_BAUD")]
+    pub serial_baud: Option<String>,
+
+    /// Address to serve the Prometheus metrics on
+    #[arg(long, env = "LISTEN_ADDR")]
+    pub listen_addr: Option<String>,
+
+    /// Interval of the requests to the smart meter in milliseconds
+    #[arg(long, env = "POLL_INTERVAL_MS")]
+    pub poll_interval_ms: Option<String>,
+
+    /// File of B_ID and B_PW in KEY=VALUE lines, which takes precedence over the environment variables B_ID and B_PW
+    #[arg(long, env = "CREDENTIALS_FILE")]
+    pub credentials_file: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_args() {
+        let args = Args::try_parse_from([
+            "smartmeter-exporter",
+            "--serial-port",
+            "/dev/ttyUSB0",
+            "--poll-interval-ms",
+            "5000",
+        ])
+        .unwrap();
+        assert_eq!(args.serial_port.as_deref(), Some("/dev/ttyUSB0"));
+        assert_eq!(args.poll_interval_ms.as_deref(), Some("5000"));
+
+        assert!(Args::try_parse_from(["smartmeter-exporter", "--unknown"]).is_err());
+        assert!(Args::try_parse_from(["smartmeter-exporter", "--b-pw", "123XXXXXXXXX"]).is_err());
+    }
+}
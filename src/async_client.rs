@@ -0,0 +1,317 @@
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_core::Stream;
+use log::{debug, warn};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf, ReadHalf, WriteHalf};
+use tokio::time::{timeout_at, Instant};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+
+use crate::command::{scan_time, Command, SCAN_CHANNEL_MASK_ALL, SKSENDTO_SEC_ENCRYPTED};
+use crate::echonet_lite::{EData, EDataFormat1, EDataProperty, EchonetLite, Eoj, EOJ_MANAGEMENT_CONTROLLER};
+use crate::client::failed;
+use crate::parser::{IpAddr, PanDesc, Response};
+use crate::response_buffer::ResponseBuffer;
+
+const READ_BUFFER_SIZE: usize = 1024;
+
+// the replies and events read from the module, which ends when the device is closed or fails
+pub struct ResponseStream<R> {
+    reader: R,
+    buf: ResponseBuffer,
+}
+
+impl<R: AsyncRead + Unpin> ResponseStream<R> {
+    pub fn new(reader: R) -> ResponseStream<R> {
+        ResponseStream {
+            reader,
+            buf: ResponseBuffer::new(),
+        }
+    }
+
+    pub async fn next(&mut self) -> Option<Response> {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+}
+
+impl<R: AsyncRead + Unpin> Stream for ResponseStream<R> {
+    type Item = Response;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Response>> {
+        let this = &mut *self;
+        loop {
+            if let Some(response) = this.buf.next_response() {
+                return Poll::Ready(Some(response));
+            }
+            let mut chunk = [0u8; READ_BUFFER_SIZE];
+            let mut read_buf = ReadBuf::new(&mut chunk);
+            match Pin::new(&mut this.reader).poll_read(cx, &mut read_buf) {
+                Poll::Ready(Ok(())) if read_buf.filled().is_empty() => return Poll::Ready(None),
+                Poll::Ready(Ok(())) => this.buf.extend(read_buf.filled()),
+                Poll::Ready(Err(e)) => {
+                    warn!("failed to read from the module: {:?}", e);
+                    return Poll::Ready(None);
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// A client of the Wi-SUN module on tokio, enabled by the `async` feature.
+// Commands are sent by async fns which wait for their reply, and the other responses are dropped meanwhile.
+pub struct AsyncSmartMeterClient<S> {
+    writer: WriteHalf<S>,
+    responses: ResponseStream<ReadHalf<S>>,
+    timeout: Duration,
+    tid: u16,
+    // the controller object the requests are sent from
+    seoj: Eoj,
+    // the address of the smartmeter after connect()
+    ipaddr: Option<IpAddr>,
+}
+
+impl AsyncSmartMeterClient<SerialStream> {
+    pub fn open(path: &str, baud_rate: u32, timeout: Duration) -> io::Result<Self> {
+        let port = tokio_serial::new(path, baud_rate).open_native_async()?;
+        Ok(AsyncSmartMeterClient::new(port, timeout))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncSmartMeterClient<S> {
+    // `timeout` is how long to wait for each reply
+    pub fn new(device: S, timeout: Duration) -> AsyncSmartMeterClient<S> {
+        let (reader, writer) = tokio::io::split(device);
+        AsyncSmartMeterClient {
+            writer,
+            responses: ResponseStream::new(reader),
+            timeout,
+            tid: 0,
+            seoj: EOJ_MANAGEMENT_CONTROLLER,
+            ipaddr: None,
+        }
+    }
+
+    pub fn ipaddr(&self) -> Option<&IpAddr> {
+        self.ipaddr.as_ref()
+    }
+
+    // for meters which only answer a specific controller instance
+    pub fn set_seoj(&mut self, seoj: Eoj) {
+        self.seoj = seoj;
+    }
+
+    // every response of the module, for the events the methods below do not wait for
+    pub fn responses(&mut self) -> &mut ResponseStream<ReadHalf<S>> {
+        &mut self.responses
+    }
+
+    pub async fn send(&mut self, command: Command<'_>) -> io::Result<()> {
+        let bytes: Bytes = command.into();
+        self.writer.write_all(&bytes).await?;
+        self.writer.flush().await
+    }
+
+    // the next reply or event of the module
+    pub async fn recv(&mut self) -> io::Result<Response> {
+        self.recv_until(Instant::now() + self.timeout).await
+    }
+
+    async fn recv_until(&mut self, deadline: Instant) -> io::Result<Response> {
+        match timeout_at(deadline, self.responses.next()).await {
+            Ok(Some(response)) => Ok(response),
+            Ok(None) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "no reply from the module")),
+        }
+    }
+
+    // wait for the reply `expected` accepts, the other responses such as events are dropped.
+    // FAIL ERnn is returned as an error.
+    async fn expect<F: Fn(&Response) -> bool>(&mut self, command: &str, expected: F) -> io::Result<Response> {
+        loop {
+            let r = self.recv().await?;
+            if let Response::Fail { .. } = r {
+                return Err(failed(command, &r));
+            }
+            if expected(&r) {
+                return Ok(r);
+            }
+            debug!("ignore {} while waiting for the reply of {}", r, command);
+        }
+    }
+
+    // scan all channels, and return the PAN with the best LQI
+    pub async fn scan(&mut self, duration: u8) -> io::Result<Option<PanDesc>> {
+        self.send(Command::ActiveScan {
+            channel_mask: SCAN_CHANNEL_MASK_ALL,
+            duration,
+        })
+        .await?;
+        let deadline = Instant::now() + scan_time(SCAN_CHANNEL_MASK_ALL, duration) + self.timeout;
+        let mut found: Option<PanDesc> = None;
+        loop {
+            match self.recv_until(deadline).await? {
+                Response::EPanDesc(pan_desc) if found.as_ref().is_none_or(|found| found.lqi < pan_desc.lqi) => {
+                    found = Some(pan_desc);
+                }
+                Response::Event { num: 0x22, .. } => return Ok(found),
+                _ => {}
+            }
+        }
+    }
+
+    // set the B-route credentials and establish the PANA session with the PAN found by scan()
+    pub async fn connect(&mut self, b_id: &str, b_pw: &str, pan_desc: &PanDesc) -> io::Result<IpAddr> {
+        self.send(Command::SkSetRbid { id: b_id }).await?;
+        self.expect("SKSETRBID", |r| matches!(r, Response::SkSetRbid { .. })).await?;
+        self.send(Command::SkSetPwd { pwd: b_pw }).await?;
+        self.expect("SKSETPWD", |r| matches!(r, Response::SkSetPwd { .. })).await?;
+        self.send(Command::SkSreg {
+            sreg: 0x02,
+            val: Some(pan_desc.channel as u32),
+        })
+        .await?;
+        self.expect("SKSREG", |r| matches!(r, Response::SkSreg { .. })).await?;
+        self.send(Command::SkSreg {
+            sreg: 0x03,
+            val: Some(pan_desc.pan_id as u32),
+        })
+        .await?;
+        self.expect("SKSREG", |r| matches!(r, Response::SkSreg { .. })).await?;
+
+        self.send(Command::SkLl64 {
+            addr64: &pan_desc.addr,
+        })
+        .await?;
+        // the echo back of SKLL64 is followed by the address, which is the only reply if echo back is disabled
+        let ipaddr = loop {
+            match self.recv().await? {
+                Response::SkLl64 { ipaddr, .. } | Response::LinkLocalAddr { ipaddr } => break ipaddr,
+                r @ Response::Fail { .. } => return Err(failed("SKLL64", &r)),
+                r => debug!("ignore {} while waiting for the reply of SKLL64", r),
+            }
+        };
+
+        self.send(Command::SkJoin { ipaddr: &ipaddr }).await?;
+        self.expect("SKJOIN", |r| matches!(r, Response::SkJoin { .. })).await?;
+        // PANA authentication takes a few seconds
+        let deadline = Instant::now() + self.timeout * 6;
+        loop {
+            match self.recv_until(deadline).await? {
+                Response::Event { num: 0x25, .. } => break,
+                r @ Response::Event { num: 0x24, .. } => return Err(failed("SKJOIN", &r)),
+                _ => {}
+            }
+        }
+
+        self.ipaddr = Some(ipaddr.clone());
+        Ok(ipaddr)
+    }
+
+    // read the properties of the smartmeter, the properties it could not serve have no data
+    pub async fn get(&mut self, epcs: &[u8]) -> io::Result<Vec<EDataProperty>> {
+        let ipaddr = self
+            .ipaddr
+            .clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "connect() first"))?;
+        self.tid = self.tid.wrapping_add(1);
+        let tid = self.tid;
+        self.send(Command::SendGetRequest {
+            ipaddr: &ipaddr,
+            sec: SKSENDTO_SEC_ENCRYPTED,
+            seoj: self.seoj,
+            tid,
+            epcs,
+        })
+        .await?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.recv_until(deadline).await? {
+                Response::ERxUdp {
+                    secured: 0x00,
+                    ref sender,
+                    ..
+                } => {
+                    warn!("ignore unsecured frame from {}", sender);
+                }
+                Response::ERxUdp {
+                    data:
+                        EchonetLite {
+                            ehd,
+                            edata: EData::EDataFormat1(EDataFormat1 { props, .. }),
+                        },
+                    ..
+                } if ehd.tid == tid => return Ok(props),
+                r @ Response::Fail { .. } => return Err(failed("SKSENDTO", &r)),
+                r @ Response::Event { num: 0x29, .. } => {
+                    self.ipaddr = None;
+                    return Err(failed("PANA session", &r));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    const METER_IPADDR: &str = "FE80:0000:0000:0000:021D:1290:1234:5678";
+
+    #[tokio::test]
+    async fn test_response_stream() {
+        let input = format!(
+            "SKJOIN {ip}\r\nOK\r\nGARBAGE\r\nFAIL ER04\r\nEVENT 25 {ip}\r\n",
+            ip = METER_IPADDR
+        );
+        let mut responses = ResponseStream::new(input.as_bytes());
+
+        assert!(matches!(responses.next().await, Some(Response::SkJoin { .. })));
+        // the unknown line is skipped
+        assert_eq!(responses.next().await, Some(Response::Fail { code: 4 }));
+        assert!(matches!(responses.next().await, Some(Response::Event { num: 0x25, .. })));
+        assert!(responses.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_async_client() {
+        let (device, mut module) = tokio::io::duplex(4096);
+        let frame = b"\x10\x81\x00\x01\x02\x88\x01\x05\xFF\x01\x72\x01\xE7\x04\x00\x00\x01\xF4";
+        let mut script = format!(
+            "SKSETRBID 00000000000000000000000000000000\r\nOK\r\nSKSETPWD C 123XXXXXXXXX\r\nOK\r\nSKSREG S2 21\r\nOK\r\nSKSREG S3 8888\r\nOK\r\nSKLL64 001D129012345678\r\n{ip}\r\nSKJOIN {ip}\r\nOK\r\nEVENT 25 {ip}\r\nERXUDP {ip} FE80:0000:0000:0000:1207:23FF:FEA8:041F 0E1A 0E1A 001D129012345678 1 {:04X} ",
+            frame.len(),
+            ip = METER_IPADDR
+        )
+        .into_bytes();
+        script.extend_from_slice(frame);
+        script.extend_from_slice(b"\r\n");
+        module.write_all(&script).await.unwrap();
+
+        let mut client = AsyncSmartMeterClient::new(device, Duration::from_millis(100));
+        let pan_desc = PanDesc {
+            channel: 0x21,
+            pan_id: 0x8888,
+            addr: "001D129012345678".to_string(),
+            ..Default::default()
+        };
+        let ipaddr = client
+            .connect("00000000000000000000000000000000", "123XXXXXXXXX", &pan_desc)
+            .await
+            .unwrap();
+        assert_eq!(ipaddr, METER_IPADDR);
+
+        let props = client.get(&[0xE7]).await.unwrap();
+        assert_eq!(props[0].edt, Bytes::from_static(b"\x00\x00\x01\xF4"));
+
+        let mut written = vec![0u8; 11];
+        module.read_exact(&mut written).await.unwrap();
+        assert_eq!(written, b"SKSETRBID 0");
+    }
+}
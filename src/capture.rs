@@ -0,0 +1,75 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+// The raw bytes read from the UART are appended as "<unix time> <bytes in hex>" per line when CAPTURE_FILE is set,
+// which can be fed to REPLAY_FILE later.
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Capture {
+    writer: BufWriter<File>,
+    last_flush: Instant,
+}
+
+impl Capture {
+    pub fn open(path: &str) -> io::Result<Capture> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Capture {
+            writer: BufWriter::new(file),
+            last_flush: Instant::now(),
+        })
+    }
+
+    pub fn write_chunk(&mut self, timestamp: f64, chunk: &[u8]) -> io::Result<()> {
+        writeln!(self.writer, "{:.3} {}", timestamp, hex::encode_upper(chunk))?;
+        if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    // also called while the UART is idle, so that the capture survives the process being killed
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.last_flush = Instant::now();
+        self.writer.flush()
+    }
+}
+
+// decode a line of the capture into the raw bytes
+pub fn parse_line(line: &str) -> Option<Vec<u8>> {
+    let (timestamp, chunk) = line.trim_end().split_once(' ')?;
+    timestamp.parse::<f64>().ok()?;
+    hex::decode(chunk).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let path = std::env::temp_dir().join(format!("capture-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut capture = Capture::open(path).unwrap();
+        capture.write_chunk(1700000000.0, b"SKRESET\r\n").unwrap();
+        capture.write_chunk(1700000000.5, b"OK\r\n").unwrap();
+        capture.flush().unwrap();
+
+        let raw = std::fs::read_to_string(path).unwrap();
+        assert_eq!(
+            raw,
+            "1700000000.000 534B52455345540D0A\n1700000000.500 4F4B0D0A\n"
+        );
+        let lines: Vec<_> = raw.lines().map(parse_line).collect();
+        assert_eq!(
+            lines,
+            vec![Some(b"SKRESET\r\n".to_vec()), Some(b"OK\r\n".to_vec())]
+        );
+        assert_eq!(parse_line("534B52455345540D0A"), None);
+        assert_eq!(parse_line("1700000000.000 XX"), None);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use log::{debug, warn};
+
+use crate::command::{scan_time, Command, SCAN_CHANNEL_MASK_ALL, SKSENDTO_SEC_ENCRYPTED};
+use crate::echonet_lite::{EData, EDataFormat1, EDataProperty, EchonetLite, Eoj, EOJ_MANAGEMENT_CONTROLLER};
+use crate::parser::{IpAddr, PanDesc, Response};
+use crate::response_buffer::ResponseBuffer;
+
+const READ_BUFFER_SIZE: usize = 1024;
+
+// A blocking client of the Wi-SUN module, which reads the replies on the calling thread.
+// The device should return from read() periodically (e.g. a serial port with a read timeout),
+// otherwise `timeout` can not be enforced.
+// This is for the other tools embedding the client, the exporter reads on a thread of its own.
+pub struct SmartMeterClient<D: Read + Write> {
+    device: D,
+    buf: ResponseBuffer,
+    timeout: Duration,
+    tid: u16,
+    // the controller object the requests are sent from
+    seoj: Eoj,
+    // the address of the smartmeter after connect()
+    ipaddr: Option<IpAddr>,
+}
+
+impl<D: Read + Write> SmartMeterClient<D> {
+    // `timeout` is how long to wait for each reply
+    pub fn new(device: D, timeout: Duration) -> SmartMeterClient<D> {
+        SmartMeterClient {
+            device,
+            buf: ResponseBuffer::new(),
+            timeout,
+            tid: 0,
+            seoj: EOJ_MANAGEMENT_CONTROLLER,
+            ipaddr: None,
+        }
+    }
+
+    pub fn ipaddr(&self) -> Option<&IpAddr> {
+        self.ipaddr.as_ref()
+    }
+
+    // for meters which only answer a specific controller instance
+    pub fn set_seoj(&mut self, seoj: Eoj) {
+        self.seoj = seoj;
+    }
+
+    pub fn send(&mut self, command: Command<'_>) -> io::Result<()> {
+        let bytes: Bytes = command.into();
+        self.device.write_all(&bytes)?;
+        self.device.flush()
+    }
+
+    // the next reply or event of the module
+    pub fn recv(&mut self) -> io::Result<Response> {
+        self.recv_until(Instant::now() + self.timeout)
+    }
+
+    fn recv_until(&mut self, deadline: Instant) -> io::Result<Response> {
+        loop {
+            if let Some(response) = self.buf.next_response() {
+                return Ok(response);
+            }
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "no reply from the module"));
+            }
+            let mut chunk = [0u8; READ_BUFFER_SIZE];
+            match self.device.read(&mut chunk) {
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => self.buf.extend(&chunk[..n]),
+                Err(e) if matches!(e.kind(), io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock) => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // wait for the reply `expected` accepts, the other responses such as events are dropped.
+    // FAIL ERnn is returned as an error.
+    fn expect<F: Fn(&Response) -> bool>(&mut self, command: &str, expected: F) -> io::Result<Response> {
+        loop {
+            let r = self.recv()?;
+            if let Response::Fail { .. } = r {
+                return Err(failed(command, &r));
+            }
+            if expected(&r) {
+                return Ok(r);
+            }
+            debug!("ignore {} while waiting for the reply of {}", r, command);
+        }
+    }
+
+    // scan all channels, and return the PAN with the best LQI
+    pub fn scan(&mut self, duration: u8) -> io::Result<Option<PanDesc>> {
+        self.send(Command::ActiveScan {
+            channel_mask: SCAN_CHANNEL_MASK_ALL,
+            duration,
+        })?;
+        let deadline = Instant::now() + scan_time(SCAN_CHANNEL_MASK_ALL, duration) + self.timeout;
+        let mut found: Option<PanDesc> = None;
+        loop {
+            match self.recv_until(deadline)? {
+                Response::EPanDesc(pan_desc) if found.as_ref().is_none_or(|found| found.lqi < pan_desc.lqi) => {
+                    found = Some(pan_desc);
+                }
+                Response::Event { num: 0x22, .. } => return Ok(found),
+                _ => {}
+            }
+        }
+    }
+
+    // set the B-route credentials and establish the PANA session with the PAN found by scan()
+    pub fn connect(&mut self, b_id: &str, b_pw: &str, pan_desc: &PanDesc) -> io::Result<IpAddr> {
+        self.send(Command::SkSetRbid { id: b_id })?;
+        self.expect("SKSETRBID", |r| matches!(r, Response::SkSetRbid { .. }))?;
+        self.send(Command::SkSetPwd { pwd: b_pw })?;
+        self.expect("SKSETPWD", |r| matches!(r, Response::SkSetPwd { .. }))?;
+        self.send(Command::SkSreg {
+            sreg: 0x02,
+            val: Some(pan_desc.channel as u32),
+        })?;
+        self.expect("SKSREG", |r| matches!(r, Response::SkSreg { .. }))?;
+        self.send(Command::SkSreg {
+            sreg: 0x03,
+            val: Some(pan_desc.pan_id as u32),
+        })?;
+        self.expect("SKSREG", |r| matches!(r, Response::SkSreg { .. }))?;
+
+        self.send(Command::SkLl64 {
+            addr64: &pan_desc.addr,
+        })?;
+        // the echo back of SKLL64 is followed by the address, which is the only reply if echo back is disabled
+        let ipaddr = loop {
+            match self.recv()? {
+                Response::SkLl64 { ipaddr, .. } | Response::LinkLocalAddr { ipaddr } => break ipaddr,
+                r @ Response::Fail { .. } => return Err(failed("SKLL64", &r)),
+                r => debug!("ignore {} while waiting for the reply of SKLL64", r),
+            }
+        };
+
+        self.send(Command::SkJoin { ipaddr: &ipaddr })?;
+        self.expect("SKJOIN", |r| matches!(r, Response::SkJoin { .. }))?;
+        // PANA authentication takes a few seconds
+        let deadline = Instant::now() + self.timeout * 6;
+        loop {
+            match self.recv_until(deadline)? {
+                Response::Event { num: 0x25, .. } => break,
+                r @ Response::Event { num: 0x24, .. } => return Err(failed("SKJOIN", &r)),
+                _ => {}
+            }
+        }
+
+        self.ipaddr = Some(ipaddr.clone());
+        Ok(ipaddr)
+    }
+
+    // read the properties of the smartmeter, the properties it could not serve have no data
+    pub fn get(&mut self, epcs: &[u8]) -> io::Result<Vec<EDataProperty>> {
+        let ipaddr = self
+            .ipaddr
+            .clone()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "connect() first"))?;
+        self.tid = self.tid.wrapping_add(1);
+        let tid = self.tid;
+        self.send(Command::SendGetRequest {
+            ipaddr: &ipaddr,
+            sec: SKSENDTO_SEC_ENCRYPTED,
+            seoj: self.seoj,
+            tid,
+            epcs,
+        })?;
+
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            match self.recv_until(deadline)? {
+                Response::ERxUdp {
+                    secured: 0x00,
+                    ref sender,
+                    ..
+                } => {
+                    warn!("ignore unsecured frame from {}", sender);
+                }
+                Response::ERxUdp {
+                    data:
+                        EchonetLite {
+                            ehd,
+                            edata: EData::EDataFormat1(EDataFormat1 { props, .. }),
+                        },
+                    ..
+                } if ehd.tid == tid => return Ok(props),
+                r @ Response::Fail { .. } => return Err(failed("SKSENDTO", &r)),
+                r @ Response::Event { num: 0x29, .. } => {
+                    self.ipaddr = None;
+                    return Err(failed("PANA session", &r));
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+pub(crate) fn failed(command: &str, r: &Response) -> io::Error {
+    io::Error::other(format!("{} failed: {}", command, r))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    const METER_IPADDR: &str = "FE80:0000:0000:0000:021D:1290:1234:5678";
+
+    // replies the scripted responses one by one for each written command
+    #[derive(Default)]
+    struct FakeDevice {
+        script: VecDeque<Vec<u8>>,
+        pending: VecDeque<u8>,
+        written: Vec<Vec<u8>>,
+    }
+
+    impl Read for FakeDevice {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pending.is_empty() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            let n = buf.len().min(self.pending.len());
+            for (dst, src) in buf.iter_mut().zip(self.pending.drain(..n)) {
+                *dst = src;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for FakeDevice {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.push(buf.to_vec());
+            if let Some(response) = self.script.pop_front() {
+                self.pending.extend(response);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_client() {
+        let frame = b"\x10\x81\x00\x01\x02\x88\x01\x05\xFF\x01\x72\x01\xE7\x04\x00\x00\x01\xF4";
+        let mut erxudp = format!(
+            "SKSENDTO 1 {ip} 0E1A 1 0 000E \r\nEVENT 21 {ip} 00\r\nOK\r\n\r\nERXUDP {ip} FE80:0000:0000:0000:1207:23FF:FEA8:041F 0E1A 0E1A 001D129012345678 1 {:04X} ",
+            frame.len(),
+            ip = METER_IPADDR
+        )
+        .into_bytes();
+        erxudp.extend_from_slice(frame);
+        erxudp.extend_from_slice(b"\r\n");
+        let device = FakeDevice {
+            script: vec![
+                format!(
+                    "SKSCAN 2 FFFFFFFF 0 0\r\nOK\r\nEPANDESC\r\n  Channel:21\r\n  Channel Page:09\r\n  Pan ID:8888\r\n  Addr:001D129012345678\r\n  LQI:E1\r\n  PairID:00AXXXXX\r\nEVENT 22 {}\r\n",
+                    METER_IPADDR
+                )
+                .into_bytes(),
+                b"SKSETRBID 00000000000000000000000000000000\r\nOK\r\n".to_vec(),
+                b"SKSETPWD C 123XXXXXXXXX\r\nOK\r\n".to_vec(),
+                b"SKSREG S2 21\r\nOK\r\n".to_vec(),
+                b"SKSREG S3 8888\r\nOK\r\n".to_vec(),
+                format!("SKLL64 001D129012345678\r\n{}\r\n", METER_IPADDR).into_bytes(),
+                format!("SKJOIN {ip}\r\nOK\r\nEVENT 25 {ip}\r\n", ip = METER_IPADDR).into_bytes(),
+                erxudp,
+            ]
+            .into(),
+            ..Default::default()
+        };
+        let mut client = SmartMeterClient::new(device, Duration::from_millis(100));
+
+        let pan_desc = client.scan(0).unwrap().unwrap();
+        assert_eq!(pan_desc.pan_id, 0x8888);
+        let ipaddr = client
+            .connect("00000000000000000000000000000000", "123XXXXXXXXX", &pan_desc)
+            .unwrap();
+        assert_eq!(ipaddr, METER_IPADDR);
+
+        let props = client.get(&[0xE7]).unwrap();
+        assert_eq!(props.len(), 1);
+        assert_eq!(props[0].edt, Bytes::from_static(b"\x00\x00\x01\xF4"));
+
+        // nothing more to read
+        assert_eq!(client.recv().unwrap_err().kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -1,24 +1,48 @@
+use std::fmt;
+use std::time::Duration;
+
 use bytes::{Bytes, BytesMut, BufMut};
 
-use crate::echonet_lite::{EchonetLite, EHd, EHD1_ECHONET_LITE, EHD2_FORMAT1, EData, EDataFormat1, EOJ_MANAGEMENT_CONTROLLER, EOJ_HOUSING_LOW_VOLTAGE_SMART_METER, Esv, EDataProperty, EpcLowVoltageSmartMeter};
+use crate::echonet_lite::{build_get_request, Eoj, Esv, EpcLowVoltageSmartMeter, RequestBuilder};
 
 pub type Addr64 = str;
 pub type IpAddr = str;
+
+// mode of WOPT/ROPT, which selects how ERXUDP payload is shown.
+// 00 is binary and 01 is hex ASCII in the Rohm command reference, not 01 for binary as the request was written.
+pub const WOPT_BINARY: u8 = 0x00;
+pub const WOPT_HEX_ASCII: u8 = 0x01;
+
+// the well-known UDP port of ECHONET Lite (3610)
+pub const ECHONET_LITE_PORT: u16 = 0x0E1A;
+
+// channel mask of SKSCAN which scans all channels
+pub const SCAN_CHANNEL_MASK_ALL: u32 = 0xFFFFFFFF;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Command<'a> {
     SkReset,
+    SkTerm,
+    SkVer,
     SkSetRbid{
         id: &'a str,
     },
     SkSetPwd {
         pwd: &'a str,
     },
+    SkRopt,
+    SkWopt {
+        mode: u8,
+    },
     ActiveScan {
+        // bit n selects channel 33 + n
+        channel_mask: u32,
         duration: u8,
     },
+    // reads the register if val is None
     SkSreg {
         sreg: u8,
-        val: u32,
+        val: Option<u32>,
     },
     SkLl64 {
         addr64: &'a Addr64,
@@ -26,11 +50,91 @@ pub enum Command<'a> {
     SkJoin {
         ipaddr: &'a IpAddr,
     },
-    SendEnergyRequest {
+    // add the meter to the neighbor cache, which some firmware needs before SKJOIN
+    SkAddNbr {
+        ipaddr: &'a IpAddr,
+        addr64: &'a Addr64,
+    },
+    SkInfo,
+    // send a pre-built UDP payload, which is ECHONET Lite frame when port is ECHONET_LITE_PORT
+    SkSendTo {
+        ipaddr: &'a IpAddr,
+        port: u16,
+        handle: u8,
+        sec: u8,
+        payload: Bytes,
+    },
+    // seoj is the controller object sending the request, usually EOJ_MANAGEMENT_CONTROLLER
+    SendGetRequest {
         ipaddr: &'a IpAddr,
+        sec: u8,
+        seoj: Eoj,
+        tid: u16,
+        epcs: &'a [u8],
     },
-    SendCumulativeEnergyUnitRequeest {
+    // SetC of EPC 0xE5, which selects the day of EPC 0xE2 (normal direction) and 0xE4 (reverse direction).
+    // Get them by SendGetRequest after Set_Res arrived, since the module sends one frame at a time.
+    SendSetDayForHistoricalData {
         ipaddr: &'a IpAddr,
+        sec: u8,
+        seoj: Eoj,
+        tid: u16,
+        day: u8,
+    },
+}
+
+// SKSCAN takes 0.96ms * (2^duration + 1) for each channel
+pub fn scan_time(channel_mask: u32, duration: u8) -> Duration {
+    Duration::from_micros(960 * ((1 << duration) + 1)) * channel_mask.count_ones()
+}
+
+// UDP handle of SKSENDTO used for the smart meter.
+// Handle 1 is bound to ECHONET_LITE_PORT by default on BP35A1.
+pub const SKSENDTO_HANDLE: u8 = 1;
+// SEC of SKSENDTO: 0 sends in plain text, 1 encrypts with the PANA session key if established
+// (and sends in plain text otherwise). Smart meters drop unencrypted frames.
+pub const SKSENDTO_SEC_PLAIN: u8 = 0;
+pub const SKSENDTO_SEC_ENCRYPTED: u8 = 1;
+
+fn send_to_meter(ipaddr: &IpAddr, sec: u8, payload: Bytes) -> Bytes {
+    Command::SkSendTo {
+        ipaddr,
+        port: ECHONET_LITE_PORT,
+        handle: SKSENDTO_HANDLE,
+        sec,
+        payload,
+    }
+    .into()
+}
+
+// the command line as sent, except that the credentials are masked and the ECHONET Lite requests are summarized
+impl fmt::Display for Command<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::SkReset => f.write_str("SKRESET"),
+            Command::SkTerm => f.write_str("SKTERM"),
+            Command::SkVer => f.write_str("SKVER"),
+            Command::SkSetRbid { .. } => f.write_str("SKSETRBID ****"),
+            Command::SkSetPwd { pwd } => write!(f, "SKSETPWD {:X} ****", pwd.len()),
+            Command::SkRopt => f.write_str("ROPT"),
+            Command::SkWopt { mode } => write!(f, "WOPT {:02X}", mode),
+            Command::ActiveScan { channel_mask, duration } => write!(f, "SKSCAN 2 {:08X} {:X} 0", channel_mask, duration),
+            Command::SkSreg { sreg, val: Some(val) } => write!(f, "SKSREG S{:X} {:X}", sreg, val),
+            Command::SkSreg { sreg, val: None } => write!(f, "SKSREG S{:X}", sreg),
+            Command::SkLl64 { addr64 } => write!(f, "SKLL64 {}", addr64),
+            Command::SkJoin { ipaddr } => write!(f, "SKJOIN {}", ipaddr),
+            Command::SkAddNbr { ipaddr, addr64 } => write!(f, "SKADDNBR {} {}", ipaddr, addr64),
+            Command::SkInfo => f.write_str("SKINFO"),
+            Command::SkSendTo { ipaddr, port, handle, sec, payload } => {
+                write!(f, "SKSENDTO {} {} {:04X} {} 0 {:04X} {}", handle, ipaddr, port, sec, payload.len(), hex::encode_upper(payload))
+            },
+            Command::SendGetRequest { ipaddr, seoj, tid, epcs, .. } => {
+                write!(f, "Get to {} tid={:04X} seoj={} epcs={}", ipaddr, tid, seoj, hex::encode_upper(epcs))
+            },
+            Command::SendSetDayForHistoricalData { ipaddr, seoj, tid, day, .. } => {
+                write!(f, "SetC to {} tid={:04X} seoj={} E5={:02X}", ipaddr, tid, seoj, day)
+            },
+        }
     }
 }
 
@@ -40,6 +144,12 @@ impl Into<Bytes> for Command<'_> {
             Command::SkReset => {
                 Bytes::from_static(b"SKRESET\r\n")
             },
+            Command::SkTerm => {
+                Bytes::from_static(b"SKTERM\r\n")
+            },
+            Command::SkVer => {
+                Bytes::from_static(b"SKVER\r\n")
+            },
             Command::SkSetRbid { id }=> {
                 let mut cmd = BytesMut::new();
                 cmd.put(&b"SKSETRBID "[..]);
@@ -56,10 +166,20 @@ impl Into<Bytes> for Command<'_> {
                 cmd.put(&b"\r\n"[..]);
                 cmd.into()
             },
-            Command::ActiveScan { duration } => {
+            Command::SkRopt => {
+                Bytes::from_static(b"ROPT\r\n")
+            },
+            Command::SkWopt { mode } => {
                 let mut cmd = BytesMut::new();
-                cmd.put(&b"SKSCAN 2 FFFFFFFF "[..]);
-                cmd.put(format!("{:X}", duration).as_bytes());
+                cmd.put(&b"WOPT "[..]);
+                cmd.put(format!("{:02X}", mode).as_bytes());
+                cmd.put(&b"\r\n"[..]);
+                cmd.into()
+            },
+            Command::ActiveScan { channel_mask, duration } => {
+                let mut cmd = BytesMut::new();
+                cmd.put(&b"SKSCAN 2 "[..]);
+                cmd.put(format!("{:08X} {:X}", channel_mask, duration).as_bytes());
                 cmd.put(&b" 0\r\n"[..]);
                 cmd.into()
             },
@@ -67,8 +187,10 @@ impl Into<Bytes> for Command<'_> {
                 let mut cmd = BytesMut::new();
                 cmd.put(&b"SKSREG S"[..]);
                 cmd.put(format!("{:X}", sreg).as_bytes());
-                cmd.put(&b" "[..]);
-                cmd.put(format!("{:X}", val).as_bytes());
+                if let Some(val) = val {
+                    cmd.put(&b" "[..]);
+                    cmd.put(format!("{:X}", val).as_bytes());
+                }
                 cmd.put(&b"\r\n"[..]);
                 cmd.into()
             },
@@ -86,59 +208,34 @@ impl Into<Bytes> for Command<'_> {
                 cmd.put(&b"\r\n"[..]);
                 cmd.into()
             },
-            Command::SendEnergyRequest { ipaddr } => {
-                // get current power consumption
-                let get_now_p = EchonetLite {
-                    ehd: EHd {
-                        ehd1: EHD1_ECHONET_LITE,
-                        ehd2: EHD2_FORMAT1,
-                        tid: 0x0001,
-                    },
-                    edata: EData::EDataFormat1(EDataFormat1 {
-                        seoj: EOJ_MANAGEMENT_CONTROLLER,
-                        deoj: EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
-                        esv: Esv::PROP_READ,
-                        opc: 0x01,
-                        props: vec![EDataProperty {
-                            epc: EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
-                            pdc: 0x00,
-                            edt: Bytes::new(),
-                        }],
-                    })
-                };
-                let get_now_p: Bytes = get_now_p.into();
-
-                let mut cmd = BytesMut::from(format!("SKSENDTO 1 {} 0E1A 1 0 {:>04X} ", ipaddr, get_now_p.len()).as_bytes());
-                cmd.put(get_now_p);
+            Command::SkAddNbr { ipaddr, addr64 } => {
+                let mut cmd = BytesMut::new();
+                cmd.put(&b"SKADDNBR "[..]);
+                cmd.put(ipaddr.as_bytes());
+                cmd.put(&b" "[..]);
+                cmd.put(addr64.as_bytes());
                 cmd.put(&b"\r\n"[..]);
                 cmd.into()
-            },Command::SendCumulativeEnergyUnitRequeest { ipaddr } => {
-                // get current power consumption
-                let get_now_p = EchonetLite {
-                    ehd: EHd {
-                        ehd1: EHD1_ECHONET_LITE,
-                        ehd2: EHD2_FORMAT1,
-                        tid: 0x0001,
-                    },
-                    edata: EData::EDataFormat1(EDataFormat1 {
-                        seoj: EOJ_MANAGEMENT_CONTROLLER,
-                        deoj: EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
-                        esv: Esv::PROP_READ,
-                        opc: 0x01,
-                        props: vec![EDataProperty {
-                            epc: EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_UNIT,
-                            pdc: 0x00,
-                            edt: Bytes::new(),
-                        }],
-                    })
-                };
-                let get_now_p: Bytes = get_now_p.into();
-
-                let mut cmd = BytesMut::from(format!("SKSENDTO 1 {} 0E1A 1 0 {:>04X} ", ipaddr, get_now_p.len()).as_bytes());
-                cmd.put(get_now_p);
+            },
+            Command::SkInfo => {
+                Bytes::from_static(b"SKINFO\r\n")
+            },
+            Command::SkSendTo { ipaddr, port, handle, sec, payload } => {
+                let mut cmd = BytesMut::from(format!("SKSENDTO {} {} {:04X} {} 0 {:>04X} ", handle, ipaddr, port, sec, payload.len()).as_bytes());
+                cmd.put(payload);
                 cmd.put(&b"\r\n"[..]);
                 cmd.into()
             },
+            Command::SendGetRequest { ipaddr, sec, seoj, tid, epcs } => {
+                send_to_meter(ipaddr, sec, build_get_request(seoj, tid, epcs).into())
+            },
+            Command::SendSetDayForHistoricalData { ipaddr, sec, seoj, tid, day } => {
+                let set_day = RequestBuilder::new(tid, Esv::PROP_WRITE)
+                    .seoj(seoj)
+                    .property(EpcLowVoltageSmartMeter::DAY_FOR_HISTORICAL_DATA, Bytes::copy_from_slice(&[day]))
+                    .build();
+                send_to_meter(ipaddr, sec, set_day.into())
+            },
         } 
     }
 }
@@ -147,6 +244,7 @@ impl Into<Bytes> for Command<'_> {
 mod tests {
 
     use super::*;
+    use crate::echonet_lite::EOJ_MANAGEMENT_CONTROLLER;
 
     #[test]
     fn test_sk_reset() {
@@ -154,6 +252,18 @@ mod tests {
         assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKRESET\r\n"));
     }
 
+    #[test]
+    fn test_sk_term() {
+        let cmd = Command::SkTerm;
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKTERM\r\n"));
+    }
+
+    #[test]
+    fn test_sk_ver() {
+        let cmd = Command::SkVer;
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKVER\r\n"));
+    }
+
     #[test]
     fn test_sk_set_rbid() {
         let cmd = Command::SkSetRbid { id: "12345678" };
@@ -164,18 +274,46 @@ mod tests {
     fn test_sk_set_pwd() {
         let cmd = Command::SkSetPwd { pwd: "123XXXXXXXXX" };
         assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSETPWD C 123XXXXXXXXX\r\n"));
+
+        // the sender wipes the password through try_into_mut, which needs the bytes not to be shared
+        let cmd: Bytes = Command::SkSetPwd { pwd: "123XXXXXXXXX" }.into();
+        assert!(cmd.try_into_mut().is_ok());
+    }
+
+    #[test]
+    fn test_sk_ropt() {
+        let cmd = Command::SkRopt;
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"ROPT\r\n"));
+    }
+
+    #[test]
+    fn test_sk_wopt() {
+        let cmd = Command::SkWopt { mode: WOPT_BINARY };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"WOPT 00\r\n"));
     }
 
     #[test]
     fn test_active_scan() {
-        let cmd = Command::ActiveScan { duration: 6 };
-        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSCAN 2 FFFFFFFF 6\r\n"));
+        let cmd = Command::ActiveScan { channel_mask: SCAN_CHANNEL_MASK_ALL, duration: 6 };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSCAN 2 FFFFFFFF 6 0\r\n"));
+
+        let cmd = Command::ActiveScan { channel_mask: 0x3C0, duration: 14 };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSCAN 2 000003C0 E 0\r\n"));
+    }
+
+    #[test]
+    fn test_scan_time() {
+        assert_eq!(scan_time(0x1, 0), Duration::from_micros(1920));
+        assert_eq!(scan_time(0xF, 6), Duration::from_micros(960 * 65 * 4));
     }
 
     #[test]
     fn test_sk_sreg() {
-        let cmd = Command::SkSreg { sreg: 0x02, val: 0x21 };
+        let cmd = Command::SkSreg { sreg: 0x02, val: Some(0x21) };
         assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSREG S2 21\r\n"));
+
+        let cmd = Command::SkSreg { sreg: 0x02, val: None };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSREG S2\r\n"));
     }
 
     #[test]
@@ -191,8 +329,62 @@ mod tests {
     }
 
     #[test]
-    fn test_send_energy_request() {
-        let cmd = Command::SendEnergyRequest { ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef" };
-        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSENDTO 1 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 1 000E \x10\x81\x00\x01\x05\xFF\x01\x02\x88\x01\x62\x01\xE7\x00\r\n"));
+    fn test_sk_add_nbr() {
+        let cmd = Command::SkAddNbr { ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef", addr64: "0123456789ABCDEF" };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKADDNBR FE80:0000:0000:0000:0123:4567:89ab:cdef 0123456789ABCDEF\r\n"));
+    }
+
+    #[test]
+    fn test_sk_info() {
+        let cmd = Command::SkInfo;
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKINFO\r\n"));
+    }
+
+    #[test]
+    fn test_sk_send_to() {
+        let cmd = Command::SkSendTo { ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef", port: ECHONET_LITE_PORT, handle: 2, sec: SKSENDTO_SEC_PLAIN, payload: Bytes::from_static(b"\x10\x81") };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSENDTO 2 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 0 0 0002 \x10\x81\r\n"));
+
+        let cmd = Command::SkSendTo { ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef", port: 0x02CC, handle: 3, sec: 1, payload: Bytes::from_static(b"\x00") };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSENDTO 3 FE80:0000:0000:0000:0123:4567:89ab:cdef 02CC 1 0 0001 \x00\r\n"));
+    }
+
+    #[test]
+    fn test_send_get_request() {
+        let epcs = [EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY, EpcLowVoltageSmartMeter::INSTANTANEOUS_CURRENT];
+        let cmd = Command::SendGetRequest { ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef", sec: SKSENDTO_SEC_ENCRYPTED, seoj: EOJ_MANAGEMENT_CONTROLLER, tid: 0x0001, epcs: &epcs };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSENDTO 1 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 1 0 0010 \x10\x81\x00\x01\x05\xFF\x01\x02\x88\x01\x62\x02\xE7\x00\xE8\x00\r\n"));
+    }
+
+    #[test]
+    fn test_send_get_request_seoj() {
+        let seoj = Eoj { class_group_code: 0x05, class_code: 0xFF, instance_code: 0x02 };
+        let cmd = Command::SendGetRequest { ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef", sec: SKSENDTO_SEC_ENCRYPTED, seoj, tid: 0x0001, epcs: &[EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY] };
+        assert_eq!(std::convert::Into::<Bytes>::into(cmd), Bytes::from_static(b"SKSENDTO 1 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 1 0 000E \x10\x81\x00\x01\x05\xFF\x02\x02\x88\x01\x62\x01\xE7\x00\r\n"));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Command::SkSetPwd { pwd: "123XXXXXXXXX" }.to_string(), "SKSETPWD C ****");
+        assert_eq!(Command::SkSreg { sreg: 0x16, val: None }.to_string(), "SKSREG S16");
+        let cmd = Command::SendGetRequest { ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef", sec: SKSENDTO_SEC_ENCRYPTED, seoj: EOJ_MANAGEMENT_CONTROLLER, tid: 0x0001, epcs: &[EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY] };
+        assert_eq!(cmd.to_string(), "Get to FE80:0000:0000:0000:0123:4567:89ab:cdef tid=0001 seoj=05FF01 epcs=E7");
+    }
+
+    #[test]
+    fn test_send_set_day_for_historical_data() {
+        let cmd = Command::SendSetDayForHistoricalData {
+            ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef",
+            sec: SKSENDTO_SEC_ENCRYPTED,
+            seoj: EOJ_MANAGEMENT_CONTROLLER,
+            tid: 0x0001,
+            day: 1,
+        };
+        assert_eq!(
+            std::convert::Into::<Bytes>::into(cmd),
+            Bytes::from_static(
+                b"SKSENDTO 1 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 1 0 000F \x10\x81\x00\x01\x05\xFF\x01\x02\x88\x01\x61\x01\xE5\x01\x01\r\n"
+            )
+        );
     }
 }
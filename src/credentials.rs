@@ -0,0 +1,49 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+
+use zeroize::{Zeroize, Zeroizing};
+
+// The credentials file has "KEY=VALUE" per line, e.g. "B_ID=..." and "B_PW=...".
+// Blank lines and lines starting with '#' are ignored.
+
+// the values are wiped when they are dropped, so that the password does not remain in the freed memory
+pub fn load(path: &str) -> Result<HashMap<String, Zeroizing<String>>, Box<dyn Error>> {
+    let mut raw = fs::read_to_string(path)?;
+    let parsed = parse(&raw);
+    raw.zeroize();
+    parsed
+}
+
+fn parse(raw: &str) -> Result<HashMap<String, Zeroizing<String>>, Box<dyn Error>> {
+    let mut values = HashMap::new();
+    for (i, line) in raw.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) => {
+                values.insert(key.trim().to_string(), Zeroizing::new(value.trim().to_string()));
+            }
+            // do not show the line, which may contain the password
+            None => return Err(format!("invalid line {} in credentials file", i + 1).into()),
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let values = parse("# B-route\nB_ID = 00000000000000000000000000000000\n\nB_PW=123XXXXXXXXX\n").unwrap();
+        assert_eq!(values.get("B_ID").unwrap().as_str(), "00000000000000000000000000000000");
+        assert_eq!(values.get("B_PW").unwrap().as_str(), "123XXXXXXXXX");
+
+        let e = parse("B_ID=0000\nB_PW 123XXXXXXXXX\n").unwrap_err();
+        assert_eq!(e.to_string(), "invalid line 2 in credentials file");
+    }
+}
@@ -1,5 +1,5 @@
 use std::fmt;
-use bytes::{Bytes, BytesMut, BufMut};
+use bytes::{Buf, Bytes, BytesMut, BufMut};
 
 
 #[derive(Debug, PartialEq)]
@@ -17,6 +17,7 @@ pub struct EHd {
 
 pub const EHD1_ECHONET_LITE: u8 = 0x10;
 pub const EHD2_FORMAT1: u8 = 0x81;
+pub const EHD2_FORMAT2: u8 = 0x82;
 
 #[derive(PartialEq, Default, Clone)]
 pub struct EDataProperty {
@@ -32,11 +33,19 @@ pub struct Eoj {
     pub instance_code: u8,
 }
 
+impl Eoj {
+    // a PAN may have several objects of the same class, which differ only in the instance code
+    pub fn is_same_class(&self, other: &Eoj) -> bool {
+        self.class_group_code == other.class_group_code && self.class_code == other.class_code
+    }
+}
+
 pub const EOJ_HOUSING_LOW_VOLTAGE_SMART_METER: Eoj = Eoj {
     class_group_code: 0x02,
     class_code: 0x88,
     instance_code: 0x01,
 };
+// the source of the requests unless RequestBuilder::seoj overrides it, the meter replies to it as DEOJ
 pub const EOJ_MANAGEMENT_CONTROLLER: Eoj = Eoj {
     class_group_code: 0x05,
     class_code: 0xFF,
@@ -47,20 +56,314 @@ pub const EOJ_MANAGEMENT_CONTROLLER: Eoj = Eoj {
 #[non_exhaustive]
 pub struct EpcLowVoltageSmartMeter;
 impl EpcLowVoltageSmartMeter {
-    pub const STATUS: u8 = 0x80;
+    pub const OPERATION_STATUS: u8 = 0x80;
+    pub const INSTALLATION_LOCATION: u8 = 0x81;
+    pub const FAULT_STATUS: u8 = 0x88;
+    pub const MANUFACTURER_CODE: u8 = 0x8A;
+    pub const PRODUCT_CODE: u8 = 0x8C;
+    pub const PRODUCTION_NUMBER: u8 = 0x8D;
+    pub const GET_PROPERTY_MAP: u8 = 0x9F;
+    pub const COEFFICIENT: u8 = 0xD3;
     pub const EFFECTIVE_DIGITS_OF_CUMULATIVE_ENERGY: u8 = 0xD7;
     pub const CUMULATIVE_ENERGY_NORMAL_DIRECTION: u8 = 0xE0;
     pub const CUMULATIVE_ENERGY_REVERSE_DIRECTION: u8 = 0xE3;
     pub const CUMULATIVE_ENERGY_UNIT: u8 = 0xE1;
+    pub const HISTORICAL_CUMULATIVE_ENERGY_NORMAL_DIRECTION: u8 = 0xE2;
+    pub const HISTORICAL_CUMULATIVE_ENERGY_REVERSE_DIRECTION: u8 = 0xE4;
+    pub const DAY_FOR_HISTORICAL_DATA: u8 = 0xE5;
     pub const INSTANTANEOUS_ENERGY: u8 = 0xE7;
     pub const INSTANTANEOUS_CURRENT: u8 = 0xE8;
     pub const CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION: u8 = 0xEA;
     pub const CUMULATIVE_ENERGY_FIXED_TIME_REVERSE_DIRECTION: u8 = 0xEB;
+
+    // PDC of the properties with fixed-size EDT, None for the others such as the property map
+    pub fn pdc(epc: u8) -> Option<u8> {
+        match epc {
+            Self::OPERATION_STATUS
+            | Self::FAULT_STATUS
+            | Self::EFFECTIVE_DIGITS_OF_CUMULATIVE_ENERGY
+            | Self::CUMULATIVE_ENERGY_UNIT
+            | Self::DAY_FOR_HISTORICAL_DATA => Some(0x01),
+            Self::MANUFACTURER_CODE => Some(0x03),
+            Self::PRODUCT_CODE | Self::PRODUCTION_NUMBER => Some(0x0c),
+            Self::COEFFICIENT
+            | Self::CUMULATIVE_ENERGY_NORMAL_DIRECTION
+            | Self::CUMULATIVE_ENERGY_REVERSE_DIRECTION
+            | Self::INSTANTANEOUS_ENERGY
+            | Self::INSTANTANEOUS_CURRENT => Some(0x04),
+            Self::CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION
+            | Self::CUMULATIVE_ENERGY_FIXED_TIME_REVERSE_DIRECTION => Some(0x0b),
+            _ => None,
+        }
+    }
+}
+
+// decode EPC 0x80 (operation status), true if the meter is operating
+pub fn operation_status(status: u8) -> Option<bool> {
+    match status {
+        0x30 => Some(true),
+        0x31 => Some(false),
+        _ => None,
+    }
+}
+
+// decode EPC 0xE7 in W, None if the property is truncated
+pub fn instantaneous_energy(edt: &Bytes) -> Option<u32> {
+    (edt.len() == 4).then(|| edt.clone().get_u32())
+}
+
+// the current of T phase in EPC 0xE8 when the meter is single-phase 2-wire
+const INSTANTANEOUS_CURRENT_NO_DATA: i16 = 0x7FFE;
+
+// Decode EPC 0xE8 (R phase and T phase in 0.1A unit) into ampere, T phase is None for single-phase 2-wire meters.
+pub fn instantaneous_current(edt: &Bytes) -> Option<(f64, Option<f64>)> {
+    if edt.len() != 4 {
+        return None;
+    }
+    let mut edt = edt.clone();
+    let current_r = edt.get_i16();
+    let current_t = edt.get_i16();
+    let current_t = (current_t != INSTANTANEOUS_CURRENT_NO_DATA).then(|| current_t as f64 * 0.1);
+    Some((current_r as f64 * 0.1, current_t))
+}
+
+// The low voltage smartmeter class has no per-phase instantaneous power, so EPC 0xE7 is always the total of the phases.
+// Estimate the power of R and T phase by splitting the total in proportion to their currents, which assumes the same
+// voltage and power factor on both phases. None if the meter has no T phase or no current flows.
+pub fn instantaneous_power_per_phase(power: u32, current_r: f64, current_t: Option<f64>) -> Option<(f64, f64)> {
+    let current_t = current_t?;
+    let total = current_r + current_t;
+    if total <= 0.0 {
+        return None;
+    }
+    Some((power as f64 * current_r / total, power as f64 * current_t / total))
+}
+
+// decode EPC 0x88 (fault status), true if the meter is faulted
+pub fn fault_status(status: u8) -> Option<bool> {
+    match status {
+        0x41 => Some(true),
+        0x42 => Some(false),
+        _ => None,
+    }
+}
+
+// a part of the manufacturer codes registered to ECHONET Consortium
+const MANUFACTURER_NAMES: &[(u32, &str)] = &[
+    (0x000005, "Sharp"),
+    (0x000006, "Mitsubishi Electric"),
+    (0x000008, "Daikin"),
+    (0x00000B, "Panasonic"),
+    (0x000016, "Toshiba"),
+];
+
+// decode EPC 0x8A (manufacturer code) into the hex code and the name if it is known
+pub fn manufacturer(edt: &Bytes) -> Option<(String, Option<&'static str>)> {
+    if edt.len() != 3 {
+        return None;
+    }
+    let code = edt.iter().fold(0u32, |acc, &b| acc << 8 | b as u32);
+    let name = MANUFACTURER_NAMES
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, name)| *name);
+    Some((format!("{:06X}", code), name))
+}
+
+// decode EPC 0x8C (product code) or 0x8D (production number), which are 12 bytes of ASCII padded with spaces or NUL.
+// Some meters fill them with binary, which is shown in hex.
+pub fn product_string(edt: &Bytes) -> Option<String> {
+    if edt.len() != 12 {
+        return None;
+    }
+    let end = edt.iter().rposition(|&b| b != b' ' && b != 0x00)?;
+    let start = edt.iter().position(|&b| b != b' ')?;
+    let trimmed = &edt[start..=end];
+    if trimmed.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        Some(String::from_utf8_lossy(trimmed).into_owned())
+    } else {
+        Some(hex::encode_upper(edt))
+    }
+}
+
+// set of EPCs in a property map, all of which are between 0x80 and 0xFF
+#[derive(PartialEq, Eq, Default, Clone, Copy)]
+pub struct PropertyMap(u128);
+
+impl PropertyMap {
+    // Decode the property map (e.g. EPC 0x9F). The first byte is the # of properties, which are listed after it
+    // if there are less than 16, otherwise the 16 bytes bitmap follows where bit j of byte i is EPC 0x80 + 0x10 * j + i.
+    pub fn decode(edt: &Bytes) -> Option<PropertyMap> {
+        let (&num, rest) = edt.split_first()?;
+        let mut map = PropertyMap::default();
+        if num < 16 {
+            if rest.len() != num as usize {
+                return None;
+            }
+            for &epc in rest {
+                if epc < 0x80 {
+                    return None;
+                }
+                map.0 |= 1u128 << (epc - 0x80);
+            }
+        } else {
+            if rest.len() != 16 {
+                return None;
+            }
+            for (i, &bits) in rest.iter().enumerate() {
+                for j in 0..8usize {
+                    if bits & (1 << j) != 0 {
+                        map.0 |= 1u128 << (0x10 * j + i);
+                    }
+                }
+            }
+        }
+        Some(map)
+    }
+
+    pub fn contains(&self, epc: u8) -> bool {
+        epc >= 0x80 && self.0 & (1u128 << (epc - 0x80)) != 0
+    }
+
+    pub fn epcs(&self) -> Vec<u8> {
+        (0x80..=0xFF).filter(|&epc| self.contains(epc)).collect()
+    }
+}
+
+impl fmt::Debug for PropertyMap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+        for epc in self.epcs() {
+            list.entry(&format_args!("{:#x}", epc));
+        }
+        list.finish()
+    }
+}
+
+// decode EPC 0x81 (installation location) into the hex code,
+// which is either a 1 byte location code or 0x00 followed by 16 bytes of the position
+pub fn installation_location(edt: &Bytes) -> Option<String> {
+    match edt.len() {
+        1 | 17 => Some(hex::encode_upper(edt)),
+        _ => None,
+    }
+}
+
+// convert EPC 0xE1 (unit for cumulative energy) into the multiplier in kWh
+pub fn cumulative_energy_unit(unit: u8) -> Option<f64> {
+    match unit {
+        0x0 => Some(1.0),
+        0x1 => Some(0.1),
+        0x2 => Some(0.01),
+        0x3 => Some(0.001),
+        0x4 => Some(0.0001),
+        0xa => Some(10.0),
+        0xb => Some(100.0),
+        0xc => Some(1000.0),
+        0xd => Some(10000.0),
+        _ => None,
+    }
+}
+
+// the true cumulative energy in kWh is the raw value multiplied by both the coefficient (EPC 0xD3) and the unit (EPC 0xE1)
+pub fn cumulative_energy(raw: u32, coefficient: u32, unit: f64) -> f64 {
+    raw as f64 * cumulative_energy_raw_unit(coefficient, unit)
+}
+
+// kWh per 1 of the raw cumulative energy. The raw value is an integer which is exact in f64,
+// while the value in kWh may not be exact since the unit (e.g. 0.1) is not a binary fraction.
+pub fn cumulative_energy_raw_unit(coefficient: u32, unit: f64) -> f64 {
+    coefficient as f64 * unit
+}
+
+// cumulative energy counter wraps around at 10^digits (EPC 0xD7) in raw value.
+// The counter only decreases when it wraps around, so the drop is close to the full range in that case.
+pub fn is_cumulative_energy_rollover(previous: f64, current: f64, digits: u8, coefficient: u32, unit: f64) -> bool {
+    let max = 10f64.powi(digits as i32) * coefficient as f64 * unit;
+    current < previous && previous - current > max / 2.0
+}
+
+// the increase of cumulative energy in kWh between two readings, which adds the range of the counter on rollover.
+// Other decreases (e.g. the meter is replaced) count as no increase, so that the derived counter never decreases.
+pub fn cumulative_energy_increase(previous: f64, current: f64, digits: Option<u8>, coefficient: u32, unit: f64) -> f64 {
+    if current >= previous {
+        return current - previous;
+    }
+    match digits {
+        Some(digits) if is_cumulative_energy_rollover(previous, current, digits, coefficient, unit) => {
+            10f64.powi(digits as i32) * coefficient as f64 * unit - previous + current
+        }
+        _ => 0.0,
+    }
+}
+
+// smartmeters in Japan report the time in JST without timezone
+const JST_OFFSET_SECS: i64 = 9 * 60 * 60;
+
+// days since 1970-01-01 of the given date in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+// decode the collection time of EPC 0xEA/0xEB (YYYY MM DD hh mm ss before the value) into unix time
+pub fn fixed_time_unix(edt: &Bytes) -> Option<i64> {
+    if edt.len() < 7 {
+        return None;
+    }
+    let mut time = edt.slice(0..7);
+    let (year, month, day) = (time.get_u16() as i64, time.get_u8() as i64, time.get_u8() as i64);
+    let (hour, minute, second) = (time.get_u8() as i64, time.get_u8() as i64, time.get_u8() as i64);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let local = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some(local - JST_OFFSET_SECS)
+}
+
+// the raw value of EPC 0xEA/0xEB after the collection time, None if the property is truncated
+pub fn fixed_time_cumulative_energy(edt: &Bytes) -> Option<u32> {
+    (edt.len() == 11).then(|| edt.slice(7..11).get_u32())
+}
+
+pub const HISTORICAL_CUMULATIVE_ENERGY_SLOTS: usize = 48;
+const HISTORICAL_CUMULATIVE_ENERGY_NO_DATA: u32 = 0xFFFFFFFE;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct HistoricalCumulativeEnergy {
+    // day of the collection, 0 = today, 1 = yesterday, ...
+    pub day: u16,
+    // raw cumulative energy of every half an hour from 00:00, None if the meter has no data for the slot
+    pub values: Vec<Option<u32>>,
+}
+
+// parse EDT of EPC 0xE2 or 0xE4 (reverse direction), which consists of the collection day (2 bytes) and 48 cumulative energy values (4 bytes each)
+pub fn parse_historical_cumulative_energy(edt: &Bytes) -> Option<HistoricalCumulativeEnergy> {
+    if edt.len() != 2 + 4 * HISTORICAL_CUMULATIVE_ENERGY_SLOTS {
+        return None;
+    }
+
+    let mut edt = edt.clone();
+    let day = edt.get_u16();
+    let values = (0..HISTORICAL_CUMULATIVE_ENERGY_SLOTS)
+        .map(|_| match edt.get_u32() {
+            HISTORICAL_CUMULATIVE_ENERGY_NO_DATA => None,
+            value => Some(value),
+        })
+        .collect();
+
+    Some(HistoricalCumulativeEnergy { day, values })
 }
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum EData {
     EDataFormat1(EDataFormat1),
+    // arbitrary message format, which is kept as is
+    EDataFormat2(Bytes),
     InvalidEData(Bytes),
 }
 
@@ -82,6 +385,17 @@ impl Esv {
     pub const PROP_READ: u8 = 0x62;
     pub const PROP_NOTIFY: u8 = 0x62;
     pub const PROP_WRITE_READ: u8 = 0x6E;
+
+    // responses when the request can not be served
+    pub const PROP_WRITE_NO_RES_SNA: u8 = 0x50;
+    pub const PROP_WRITE_SNA: u8 = 0x51;
+    pub const PROP_READ_SNA: u8 = 0x52;
+    pub const PROP_NOTIFY_SNA: u8 = 0x53;
+    pub const PROP_WRITE_READ_SNA: u8 = 0x5E;
+
+    pub fn is_sna(esv: u8) -> bool {
+        (0x50..=0x5F).contains(&esv)
+    }
 }
 
 impl fmt::Debug for EHd {
@@ -126,6 +440,51 @@ impl fmt::Debug for EDataFormat1 {
     }
 }
 
+// concise forms for the logs, e.g. "tid=0001 seoj=028801 deoj=05FF01 esv=72 INSTANT=450W"
+
+impl fmt::Display for Eoj {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02X}{:02X}{:02X}", self.class_group_code, self.class_code, self.instance_code)
+    }
+}
+
+impl fmt::Display for EDataProperty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.epc {
+            EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY => match instantaneous_energy(&self.edt) {
+                Some(watts) => write!(f, "INSTANT={}W", watts),
+                None => write!(f, "INSTANT={}", hex::encode_upper(&self.edt)),
+            },
+            epc => write!(f, "{:02X}={}", epc, hex::encode_upper(&self.edt)),
+        }
+    }
+}
+
+impl fmt::Display for EDataFormat1 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "seoj={} deoj={} esv={:02X}", self.seoj, self.deoj, self.esv)?;
+        for prop in &self.props {
+            write!(f, " {}", prop)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for EData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EData::EDataFormat1(format1) => fmt::Display::fmt(format1, f),
+            EData::EDataFormat2(bytes) => write!(f, "format2 {}", hex::encode_upper(bytes)),
+            EData::InvalidEData(bytes) => write!(f, "invalid {}", hex::encode_upper(bytes)),
+        }
+    }
+}
+
+impl fmt::Display for EchonetLite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tid={:04X} {}", self.ehd.tid, self.edata)
+    }
+}
 
 impl Into<Bytes> for EHd {
     fn into(self) -> Bytes {
@@ -176,6 +535,7 @@ impl Into<Bytes> for EData {
     fn into(self) -> Bytes {
         match self {
             EData::EDataFormat1(data) => data.into(),
+            EData::EDataFormat2(data) => data,
             EData::InvalidEData(data) => data,
         }
     }
@@ -190,10 +550,102 @@ impl Into<Bytes> for EchonetLite {
     }
 }
 
+// builds a request frame from the management controller to the smart meter.
+// the meter echoes back the TID, which is used to match the response to the request
+pub struct RequestBuilder {
+    tid: u16,
+    esv: u8,
+    seoj: Eoj,
+    props: Vec<EDataProperty>,
+}
+
+impl RequestBuilder {
+    pub fn new(tid: u16, esv: u8) -> RequestBuilder {
+        RequestBuilder {
+            tid,
+            esv,
+            seoj: EOJ_MANAGEMENT_CONTROLLER,
+            props: Vec::new(),
+        }
+    }
+
+    // another controller instance, for meters which only answer a specific one
+    pub fn seoj(mut self, seoj: Eoj) -> RequestBuilder {
+        self.seoj = seoj;
+        self
+    }
+
+    // a property without data, as used in Get requests
+    pub fn epc(self, epc: u8) -> RequestBuilder {
+        self.property(epc, Bytes::new())
+    }
+
+    pub fn property(mut self, epc: u8, edt: Bytes) -> RequestBuilder {
+        self.props.push(EDataProperty {
+            epc,
+            pdc: edt.len() as u8,
+            edt,
+        });
+        self
+    }
+
+    pub fn build(self) -> EchonetLite {
+        EchonetLite {
+            ehd: EHd {
+                ehd1: EHD1_ECHONET_LITE,
+                ehd2: EHD2_FORMAT1,
+                tid: self.tid,
+            },
+            edata: EData::EDataFormat1(EDataFormat1 {
+                seoj: self.seoj,
+                deoj: EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+                esv: self.esv,
+                opc: self.props.len() as u8,
+                props: self.props,
+            }),
+        }
+    }
+}
+
+// build a Get request for the smart meter containing all of the given EPCs in a single frame
+pub fn build_get_request(seoj: Eoj, tid: u16, epcs: &[u8]) -> EchonetLite {
+    epcs.iter()
+        .fold(RequestBuilder::new(tid, Esv::PROP_READ).seoj(seoj), |builder, &epc| builder.epc(epc))
+        .build()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_build_get_request() {
+        let epcs = [
+            EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+            EpcLowVoltageSmartMeter::INSTANTANEOUS_CURRENT,
+            EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION,
+        ];
+        let bytes: Bytes = build_get_request(EOJ_MANAGEMENT_CONTROLLER, 0x1234, &epcs).into();
+        assert_eq!(bytes, Bytes::from_static(b"\x10\x81\x12\x34\x05\xFF\x01\x02\x88\x01\x62\x03\xE7\x00\xE8\x00\xEA\x00"));
+    }
+
+    #[test]
+    fn test_request_builder_set() {
+        let bytes: Bytes = RequestBuilder::new(0x0001, Esv::PROP_WRITE)
+            .property(EpcLowVoltageSmartMeter::DAY_FOR_HISTORICAL_DATA, Bytes::from_static(b"\x01"))
+            .build()
+            .into();
+        assert_eq!(bytes, Bytes::from_static(b"\x10\x81\x00\x01\x05\xFF\x01\x02\x88\x01\x61\x01\xE5\x01\x01"));
+    }
+
+    #[test]
+    fn test_esv_is_sna() {
+        assert!(Esv::is_sna(Esv::PROP_READ_SNA));
+        assert!(Esv::is_sna(Esv::PROP_WRITE_READ_SNA));
+        assert!(!Esv::is_sna(Esv::PROP_READ));
+        assert!(!Esv::is_sna(0x72));
+    }
+
     #[test]
     fn test_echonet_lite_as_bytes() {
         let data = EchonetLite {
@@ -220,4 +672,278 @@ mod tests {
         assert_eq!(bytes, Bytes::from_static(b"\x10\x81\x00\x01\x05\xFF\x01\x02\x88\x01\x62\x01\xE7\x00"));
     }
 
+    #[test]
+    fn test_echonet_lite_display() {
+        let data = EchonetLite {
+            ehd: EHd {
+                ehd1: EHD1_ECHONET_LITE,
+                ehd2: EHD2_FORMAT1,
+                tid: 0x0001,
+            },
+            edata: EData::EDataFormat1(EDataFormat1 {
+                seoj: EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+                deoj: EOJ_MANAGEMENT_CONTROLLER,
+                esv: 0x72,
+                opc: 0x02,
+                props: vec![
+                    EDataProperty {
+                        epc: EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+                        pdc: 0x04,
+                        edt: Bytes::from_static(b"\x00\x00\x01\xC2"),
+                    },
+                    EDataProperty {
+                        epc: EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_UNIT,
+                        pdc: 0x01,
+                        edt: Bytes::from_static(b"\x01"),
+                    },
+                ],
+            }),
+        };
+        assert_eq!(data.to_string(), "tid=0001 seoj=028801 deoj=05FF01 esv=72 INSTANT=450W E1=01");
+    }
+
+    #[test]
+    fn test_eoj_is_same_class() {
+        let other_instance = Eoj {
+            instance_code: 0x02,
+            ..EOJ_HOUSING_LOW_VOLTAGE_SMART_METER
+        };
+        assert!(other_instance.is_same_class(&EOJ_HOUSING_LOW_VOLTAGE_SMART_METER));
+        assert!(!EOJ_MANAGEMENT_CONTROLLER.is_same_class(&EOJ_HOUSING_LOW_VOLTAGE_SMART_METER));
+    }
+
+    #[test]
+    fn test_operation_status() {
+        assert_eq!(operation_status(0x30), Some(true));
+        assert_eq!(operation_status(0x31), Some(false));
+        assert_eq!(operation_status(0x00), None);
+    }
+
+    #[test]
+    fn test_instantaneous_energy() {
+        assert_eq!(instantaneous_energy(&Bytes::from_static(b"\x00\x00\x01\xf4")), Some(500));
+        // PDC 0x04 with only 2 bytes of EDT
+        assert_eq!(instantaneous_energy(&Bytes::from_static(b"\x01\xf4")), None);
+    }
+
+    #[test]
+    fn test_instantaneous_current() {
+        // single-phase 3-wire / three-phase 3-wire: 12.3A and 8.5A
+        assert_eq!(
+            instantaneous_current(&Bytes::from_static(b"\x00\x7b\x00\x55")),
+            Some((12.3, Some(8.5)))
+        );
+        // single-phase 2-wire meter has no T phase
+        assert_eq!(
+            instantaneous_current(&Bytes::from_static(b"\x00\x7b\x7f\xfe")),
+            Some((12.3, None))
+        );
+        assert_eq!(instantaneous_current(&Bytes::from_static(b"\x00\x7b")), None);
+    }
+
+    #[test]
+    fn test_instantaneous_power_per_phase() {
+        assert_eq!(instantaneous_power_per_phase(900, 10.0, Some(20.0)), Some((300.0, 600.0)));
+        assert_eq!(instantaneous_power_per_phase(900, 10.0, Some(0.0)), Some((900.0, 0.0)));
+        // single-phase 2-wire meter
+        assert_eq!(instantaneous_power_per_phase(900, 10.0, None), None);
+        assert_eq!(instantaneous_power_per_phase(0, 0.0, Some(0.0)), None);
+    }
+
+    #[test]
+    fn test_fault_status() {
+        assert_eq!(fault_status(0x41), Some(true));
+        assert_eq!(fault_status(0x42), Some(false));
+        assert_eq!(fault_status(0x00), None);
+    }
+
+    #[test]
+    fn test_manufacturer() {
+        assert_eq!(
+            manufacturer(&Bytes::from_static(b"\x00\x00\x16")),
+            Some(("000016".to_string(), Some("Toshiba")))
+        );
+        assert_eq!(
+            manufacturer(&Bytes::from_static(b"\x00\xAB\xCD")),
+            Some(("00ABCD".to_string(), None))
+        );
+        assert_eq!(manufacturer(&Bytes::from_static(b"\x00\x16")), None);
+    }
+
+    #[test]
+    fn test_property_map_list() {
+        let map = PropertyMap::decode(&Bytes::from_static(b"\x03\x80\xe7\xe8")).unwrap();
+        assert_eq!(map.epcs(), vec![0x80, 0xe7, 0xe8]);
+        assert!(map.contains(EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY));
+        assert!(!map.contains(EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_REVERSE_DIRECTION));
+        assert!(!map.contains(0x00));
+
+        assert_eq!(PropertyMap::decode(&Bytes::from_static(b"\x03\x80\xe7")), None);
+        assert_eq!(PropertyMap::decode(&Bytes::from_static(b"\x01\x10")), None);
+        assert_eq!(PropertyMap::decode(&Bytes::new()), None);
+    }
+
+    #[test]
+    fn test_property_map_bitmap() {
+        // 0x80, 0x81, 0x88, 0x8A, 0x9F, 0xD3, 0xD7, 0xE0-0xE8, 0xEA, 0xEB
+        let mut edt = BytesMut::new();
+        edt.put_u8(17);
+        edt.put_slice(&[
+            0x41, // 0x80, 0xE0
+            0x41, // 0x81, 0xE1
+            0x40, // 0xE2
+            0x60, // 0xD3, 0xE3
+            0x40, // 0xE4
+            0x40, // 0xE5
+            0x00,
+            0x60, // 0xD7, 0xE7
+            0x41, // 0x88, 0xE8
+            0x00,
+            0x41, // 0x8A, 0xEA
+            0x40, // 0xEB
+            0x00,
+            0x00,
+            0x00,
+            0x02, // 0x9F
+        ]);
+        let map = PropertyMap::decode(&edt.freeze()).unwrap();
+        assert_eq!(
+            map.epcs(),
+            vec![
+                0x80, 0x81, 0x88, 0x8A, 0x9F, 0xD3, 0xD7, 0xE0, 0xE1, 0xE2, 0xE3, 0xE4, 0xE5, 0xE7, 0xE8, 0xEA,
+                0xEB
+            ]
+        );
+
+        assert_eq!(PropertyMap::decode(&Bytes::from_static(b"\x10\x01")), None);
+    }
+
+    #[test]
+    fn test_product_string() {
+        assert_eq!(product_string(&Bytes::from_static(b"SM-1234     ")), Some("SM-1234".to_string()));
+        assert_eq!(product_string(&Bytes::from_static(b"00012345\0\0\0\0")), Some("00012345".to_string()));
+        assert_eq!(
+            product_string(&Bytes::from_static(b"\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c")),
+            Some("0102030405060708090A0B0C".to_string())
+        );
+        assert_eq!(product_string(&Bytes::from_static(b"            ")), None);
+        // meters which do not support it return PDC 0
+        assert_eq!(product_string(&Bytes::new()), None);
+    }
+
+    #[test]
+    fn test_installation_location() {
+        assert_eq!(installation_location(&Bytes::from_static(b"\x08")), Some("08".to_string()));
+        let position = Bytes::from_static(b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\x10");
+        assert_eq!(
+            installation_location(&position),
+            Some("000102030405060708090A0B0C0D0E0F10".to_string())
+        );
+        // meters which do not support it return PDC 0
+        assert_eq!(installation_location(&Bytes::new()), None);
+    }
+
+    #[test]
+    fn test_fixed_time_unix() {
+        // 2023-04-15 12:30:00 JST = 2023-04-15 03:30:00 UTC
+        let edt = Bytes::from_static(b"\x07\xe7\x04\x0f\x0c\x1e\x00\x00\x00\x1e\x61");
+        assert_eq!(fixed_time_unix(&edt), Some(1681529400));
+        assert_eq!(fixed_time_unix(&Bytes::from_static(b"\x07\xb2\x01\x01\x09\x00\x00")), Some(0));
+        assert_eq!(fixed_time_unix(&Bytes::from_static(b"\x07\xe7\x0d\x0f\x0c\x1e\x00")), None);
+        assert_eq!(fixed_time_unix(&Bytes::from_static(b"\x07\xe7")), None);
+    }
+
+    #[test]
+    fn test_fixed_time_cumulative_energy() {
+        let edt = Bytes::from_static(b"\x07\xe7\x04\x0f\x0c\x1e\x00\x00\x00\x1e\x61");
+        assert_eq!(fixed_time_cumulative_energy(&edt), Some(0x1e61));
+        // PDC 0x0b with the value cut off
+        assert_eq!(fixed_time_cumulative_energy(&edt.slice(0..9)), None);
+    }
+
+    #[test]
+    fn test_cumulative_energy_unit() {
+        assert_eq!(cumulative_energy_unit(0x1), Some(0.1));
+        assert_eq!(cumulative_energy_unit(0xa), Some(10.0));
+        assert_eq!(cumulative_energy_unit(0x5), None);
+    }
+
+    #[test]
+    fn test_cumulative_energy_with_coefficient() {
+        assert_eq!(cumulative_energy(12345, 1, 0.1), 1234.5);
+        assert_eq!(cumulative_energy(12345, 10, 0.1), 12345.0);
+        assert_eq!(cumulative_energy(12345, 100, 1.0), 1234500.0);
+    }
+
+    #[test]
+    fn test_cumulative_energy_raw_near_rollover() {
+        // 8 digits with 0.0001kWh, just before wrapping around
+        let (digits, coefficient, unit) = (8, 1, 0.0001);
+        let (previous, current) = (99_999_998, 99_999_999);
+
+        // the step of 1 in raw value is not exactly 0.0001kWh in kWh
+        let step = cumulative_energy(current, coefficient, unit) - cumulative_energy(previous, coefficient, unit);
+        assert_ne!(step, 0.0001);
+        assert!((step - 0.0001).abs() < 1e-10);
+        // while it is exact in raw value
+        assert_eq!(current as f64 - previous as f64, 1.0);
+
+        // wrapping around from 99999999 to 0 is 1 in raw value, but only approximately 0.0001kWh
+        let increase = cumulative_energy_increase(
+            cumulative_energy(current, coefficient, unit),
+            cumulative_energy(0, coefficient, unit),
+            Some(digits),
+            coefficient,
+            unit,
+        );
+        assert_ne!(increase, 0.0001);
+        assert!((increase - 0.0001).abs() < 1e-10);
+        assert_eq!(cumulative_energy_increase(current as f64, 0.0, Some(digits), 1, 1.0), 1.0);
+    }
+
+    #[test]
+    fn test_is_cumulative_energy_rollover() {
+        // 6 digits with 0.1kWh: wraps around at 100000.0kWh
+        assert!(is_cumulative_energy_rollover(99999.9, 0.2, 6, 1, 0.1));
+        assert!(!is_cumulative_energy_rollover(1234.5, 1234.6, 6, 1, 0.1));
+        assert!(!is_cumulative_energy_rollover(1234.5, 1234.5, 6, 1, 0.1));
+        // small decrease is not a rollover
+        assert!(!is_cumulative_energy_rollover(1234.5, 1234.4, 6, 1, 0.1));
+    }
+
+    #[test]
+    fn test_cumulative_energy_increase() {
+        assert_eq!(cumulative_energy_increase(1234.0, 1235.5, Some(6), 1, 0.1), 1.5);
+        assert_eq!(cumulative_energy_increase(1234.0, 1234.0, Some(6), 1, 0.1), 0.0);
+        // wraps around at 100000.0kWh
+        assert_eq!(cumulative_energy_increase(99999.0, 1.0, Some(6), 1, 0.1), 2.0);
+        // not a rollover, or the digits are unknown
+        assert_eq!(cumulative_energy_increase(1234.5, 1234.4, Some(6), 1, 0.1), 0.0);
+        assert_eq!(cumulative_energy_increase(99999.0, 1.0, None, 1, 0.1), 0.0);
+    }
+
+    #[test]
+    fn test_parse_historical_cumulative_energy() {
+        let mut edt = BytesMut::new();
+        edt.put_u16(0x0001);
+        for i in 0..HISTORICAL_CUMULATIVE_ENERGY_SLOTS as u32 - 1 {
+            edt.put_u32(1000 + i);
+        }
+        edt.put_u32(HISTORICAL_CUMULATIVE_ENERGY_NO_DATA);
+        let edt = edt.freeze();
+        assert_eq!(edt.len(), 194);
+
+        let history = parse_historical_cumulative_energy(&edt).unwrap();
+        assert_eq!(history.day, 1);
+        assert_eq!(history.values.len(), HISTORICAL_CUMULATIVE_ENERGY_SLOTS);
+        assert_eq!(history.values[0], Some(1000));
+        assert_eq!(history.values[46], Some(1046));
+        assert_eq!(history.values[47], None);
+    }
+
+    #[test]
+    fn test_parse_historical_cumulative_energy_invalid_length() {
+        assert_eq!(parse_historical_cumulative_energy(&Bytes::from_static(b"\x00\x01\x00\x00\x00\x01")), None);
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,143 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::mpsc::RecvError;
+use std::time::Duration;
+
+// why the initialization failed, which labels counter_error_initialize
+#[derive(Debug)]
+pub enum InitError {
+    // the serial port or the replay file could not be opened
+    Open(Box<dyn Error>),
+    // the module did not reply to the command in time
+    CommandTimeout { command: String, timeout: Duration },
+    // the module replied something else than the expected reply of the command
+    CommandFailed(&'static str),
+    // the module replied FAIL ERnn to the command
+    CommandRejected { command: String, code: u8 },
+    // the register read back differs from the value written
+    RegisterMismatch { sreg: u8, expected: u32, actual: u32 },
+    ResetFailed,
+    RbidRejected,
+    PwdRejected,
+    // every active scan completed without finding any PAN, i.e. no smartmeter is in range
+    ScanEmpty,
+    // the active scan did not complete in time
+    ScanFailed,
+    // EVENT 0x24
+    JoinFailed,
+    // neither EVENT 0x24 nor 0x25 in time
+    JoinTimeout,
+    // SKSENDTO of the initial request failed
+    SendFailed,
+    InvalidUnit(u8),
+    // the smartmeter did not reply EPC 0xE1
+    UnitMissing,
+    // the reader thread closed or the UART could not be written
+    Disconnected(Box<dyn Error>),
+}
+
+impl InitError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            InitError::Open(_) => "open",
+            InitError::CommandTimeout { .. } => "command_timeout",
+            InitError::CommandFailed(_) => "command_failed",
+            InitError::CommandRejected { .. } => "command_rejected",
+            InitError::RegisterMismatch { .. } => "register_mismatch",
+            InitError::ResetFailed => "reset_failed",
+            InitError::RbidRejected => "rbid_rejected",
+            InitError::PwdRejected => "pwd_rejected",
+            InitError::ScanEmpty => "scan_empty",
+            InitError::ScanFailed => "scan_failed",
+            InitError::JoinFailed => "join_failed",
+            InitError::JoinTimeout => "join_timeout",
+            InitError::SendFailed => "send_failed",
+            InitError::InvalidUnit(_) => "invalid_unit",
+            InitError::UnitMissing => "unit_missing",
+            InitError::Disconnected(_) => "disconnected",
+        }
+    }
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::Open(e) => write!(f, "failed to open UART: {}", e),
+            InitError::CommandTimeout { command, timeout } => {
+                write!(f, "{} timed out after {:?}", command, timeout)
+            }
+            InitError::CommandFailed(command) => write!(f, "{} failed", command),
+            InitError::CommandRejected { command, code } => {
+                write!(f, "{} failed with ER{:02}", command, code)
+            }
+            InitError::RegisterMismatch {
+                sreg,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "S{:X} is {:#x} after writing {:#x}",
+                sreg, actual, expected
+            ),
+            InitError::ResetFailed => write!(f, "SKRESET failed"),
+            InitError::RbidRejected => write!(f, "SKSETRBID failed"),
+            InitError::PwdRejected => write!(f, "SKSETPWD failed"),
+            InitError::ScanEmpty => write!(f, "no PAN found by active scan"),
+            InitError::ScanFailed => write!(f, "active scan did not complete"),
+            InitError::JoinFailed => write!(f, "failed to connect to PANA"),
+            InitError::JoinTimeout => write!(f, "connect timeout"),
+            InitError::SendFailed => write!(f, "Send cumulative energy unit request failed"),
+            InitError::InvalidUnit(unit) => write!(f, "Invalid cumulative energy unit {:#x}", unit),
+            InitError::UnitMissing => write!(f, "Get cumulative energy unit failed"),
+            InitError::Disconnected(e) => write!(f, "UART disconnected: {}", e),
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            InitError::Open(e) | InitError::Disconnected(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// UartWriter::send_command only fails when the UART is gone
+impl From<Box<dyn Error>> for InitError {
+    fn from(e: Box<dyn Error>) -> InitError {
+        InitError::Disconnected(e)
+    }
+}
+
+impl From<RecvError> for InitError {
+    fn from(e: RecvError) -> InitError {
+        InitError::Disconnected(Box::new(e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_error() {
+        let e = InitError::CommandTimeout {
+            command: "SKRESET".to_string(),
+            timeout: Duration::from_millis(100),
+        };
+        assert_eq!(e.kind(), "command_timeout");
+        assert_eq!(e.to_string(), "SKRESET timed out after 100ms");
+
+        let e = InitError::CommandRejected {
+            command: "SKSREG".to_string(),
+            code: 6,
+        };
+        assert_eq!(e.kind(), "command_rejected");
+        assert_eq!(e.to_string(), "SKSREG failed with ER06");
+
+        let e = InitError::from(RecvError);
+        assert_eq!(e.kind(), "disconnected");
+        assert!(e.source().is_some());
+    }
+}
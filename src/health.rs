@@ -0,0 +1,156 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+use prometheus_exporter::prometheus::Gauge;
+
+use crate::metrics::ConnectionState;
+
+const HEALTH_READ_TIMEOUT: Duration = Duration::from_secs(5);
+// only the request line is read, which is short for the paths served here
+const MAX_REQUEST_LINE_LEN: u64 = 1024;
+
+// what the request line asks for
+#[derive(Debug, PartialEq, Eq)]
+enum Route {
+    Health,
+    Index,
+    NotFound,
+}
+
+// the gauges shown on the page at /
+pub struct Status {
+    pub connection_state: Gauge,
+    pub instantaneous_energy: Gauge,
+    pub cumulative_energy: Gauge,
+    pub last_reading_unixtime: Gauge,
+}
+
+// serves `path` on `addr`, which returns 200 while the PANA session is up and 503 otherwise,
+// and a page with the latest reading at / for a quick check in a browser.
+// prometheus_exporter can not serve other paths, so this is a tiny server of its own.
+pub fn start(addr: SocketAddr, path: String, status: Status) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("serving health check on http://{}{}", addr, path);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle(stream, &path, &status) {
+                        debug!("failed to handle health check: {:?}", e);
+                    }
+                }
+                Err(e) => warn!("failed to accept health check connection: {:?}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, path: &str, gauges: &Status) -> io::Result<()> {
+    stream.set_read_timeout(Some(HEALTH_READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    // a client which never sends a newline can not make the line grow without bound
+    BufReader::new((&stream).take(MAX_REQUEST_LINE_LEN)).read_line(&mut request_line)?;
+
+    let state = gauges.connection_state.get();
+    let (status, content_type, body) = match route(&request_line, path) {
+        Route::Health => {
+            let status = health_status(state == ConnectionState::Connected as i32 as f64);
+            (status, "text/plain", status.to_string())
+        }
+        Route::Index => {
+            let body = index(
+                gauges.instantaneous_energy.get(),
+                gauges.cumulative_energy.get(),
+                state_name(state),
+                gauges.last_reading_unixtime.get(),
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs_f64())
+                    .unwrap_or(0.0),
+            );
+            ("200 OK", "text/html; charset=utf-8", body)
+        }
+        Route::NotFound => ("404 Not Found", "text/plain", "404 Not Found".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+// `path` is served as the health check even if it is /
+fn route(request_line: &str, path: &str) -> Route {
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some(p)) if p == path => Route::Health,
+        (Some("GET"), Some("/")) => Route::Index,
+        _ => Route::NotFound,
+    }
+}
+
+fn state_name(state: f64) -> &'static str {
+    match state as i32 {
+        s if s == ConnectionState::Scanning as i32 => "scanning",
+        s if s == ConnectionState::Joining as i32 => "joining",
+        s if s == ConnectionState::Connected as i32 => "connected",
+        _ => "disconnected",
+    }
+}
+
+fn index(watt: f64, kwh: f64, state: &str, last_reading: f64, now: f64) -> String {
+    let last_reading = if last_reading > 0.0 {
+        format!("{:.0} ({:.0} seconds ago)", last_reading, now - last_reading)
+    } else {
+        "none".to_string()
+    };
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><meta http-equiv=\"refresh\" content=\"10\"><title>smartmeter-exporter</title></head>\n<body>\n<h1>smartmeter-exporter</h1>\n<table>\n<tr><th>instantaneous energy</th><td>{:.0} W</td></tr>\n<tr><th>cumulative energy</th><td>{} kWh</td></tr>\n<tr><th>connection state</th><td>{}</td></tr>\n<tr><th>last reading</th><td>{}</td></tr>\n</table>\n</body>\n</html>\n",
+        watt, kwh, state, last_reading
+    )
+}
+
+fn health_status(connected: bool) -> &'static str {
+    if connected {
+        "200 OK"
+    } else {
+        "503 Service Unavailable"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        assert_eq!(route("GET /healthz HTTP/1.1\r\n", "/healthz"), Route::Health);
+        assert_eq!(route("GET / HTTP/1.1\r\n", "/healthz"), Route::Index);
+        assert_eq!(route("GET / HTTP/1.1\r\n", "/"), Route::Health);
+        assert_eq!(route("POST / HTTP/1.1\r\n", "/healthz"), Route::NotFound);
+        assert_eq!(route("GET /metrics HTTP/1.1\r\n", "/healthz"), Route::NotFound);
+        assert_eq!(route("", "/healthz"), Route::NotFound);
+        assert_eq!(health_status(true), "200 OK");
+        assert_eq!(health_status(false), "503 Service Unavailable");
+    }
+
+    #[test]
+    fn test_index() {
+        assert_eq!(state_name(ConnectionState::Connected as i32 as f64), "connected");
+
+        let page = index(512.0, 1234.5, "connected", 1700000000.0, 1700000005.0);
+        assert!(page.contains("<td>512 W</td>"));
+        assert!(page.contains("<td>1234.5 kWh</td>"));
+        assert!(page.contains("<td>connected</td>"));
+        assert!(page.contains("<td>1700000000 (5 seconds ago)</td>"));
+        assert!(index(0.0, 0.0, "scanning", 0.0, 1700000005.0).contains("<td>none</td>"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use prometheus_exporter::prometheus::Gauge;
+
+use crate::Reading;
+
+const INFLUX_MEASUREMENT: &str = "smartmeter";
+const INFLUX_WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+// # of lines waiting to be written, the rest are dropped while InfluxDB is slow or unreachable
+const INFLUX_QUEUE_CAPACITY: usize = 16;
+
+// writes readings to InfluxDB in line protocol.
+// Lines are POSTed by a background thread, so that a slow InfluxDB never stalls polling the smartmeter.
+pub struct InfluxWriter {
+    sender: SyncSender<String>,
+    counter_error: Gauge,
+}
+
+impl InfluxWriter {
+    // returns None if INFLUX_URL or INFLUX_BUCKET is not set
+    pub fn from_env(counter_error: Gauge) -> Option<InfluxWriter> {
+        let url = std::env::var("INFLUX_URL").ok()?;
+        let bucket = match std::env::var("INFLUX_BUCKET") {
+            Ok(bucket) => bucket,
+            Err(_) => {
+                warn!("INFLUX_BUCKET is not set, InfluxDB output is disabled");
+                return None;
+            }
+        };
+        let org = std::env::var("INFLUX_ORG").ok();
+        let token = std::env::var("INFLUX_TOKEN").ok();
+
+        let (sender, receiver) = sync_channel::<String>(INFLUX_QUEUE_CAPACITY);
+        let thread_counter_error = counter_error.clone();
+        let thread_url = url.clone();
+        thread::spawn(move || {
+            for line in receiver {
+                let request = write_request(&thread_url, &bucket, org.as_deref(), token.as_deref());
+                match request.send_string(&line) {
+                    Ok(_) => debug!("wrote {:?} to InfluxDB", line),
+                    Err(e) => {
+                        warn!("failed to write to InfluxDB, dropped {:?}: {:?}", line, e);
+                        thread_counter_error.inc();
+                    }
+                }
+            }
+        });
+
+        info!("writing readings to InfluxDB {}", url);
+        Some(InfluxWriter {
+            sender,
+            counter_error,
+        })
+    }
+
+    pub fn write(&self, reading: &Reading) {
+        match self.sender.try_send(line_protocol(reading)) {
+            Ok(()) => {}
+            Err(TrySendError::Full(line)) => {
+                warn!("InfluxDB queue is full, dropped {:?}", line);
+                self.counter_error.inc();
+            }
+            Err(TrySendError::Disconnected(line)) => {
+                warn!("InfluxDB writer thread closed, dropped {:?}", line);
+                self.counter_error.inc();
+            }
+        }
+    }
+}
+
+// bucket and org are percent-encoded by query(), since they may contain any character
+fn write_request(url: &str, bucket: &str, org: Option<&str>, token: Option<&str>) -> ureq::Request {
+    let mut request = ureq::post(&format!("{}/api/v2/write", url.trim_end_matches('/')))
+        .query("bucket", bucket)
+        .query("precision", "s")
+        .timeout(INFLUX_WRITE_TIMEOUT);
+    if let Some(org) = org {
+        request = request.query("org", org);
+    }
+    if let Some(token) = token {
+        request = request.set("Authorization", &format!("Token {}", token));
+    }
+    request
+}
+
+fn line_protocol(reading: &Reading) -> String {
+    format!(
+        "{} instantaneous={},cumulative={},cumulative_reverse={} {}",
+        INFLUX_MEASUREMENT,
+        reading.instantaneous_watt,
+        reading.cumulative_kwh,
+        reading.cumulative_reverse_kwh,
+        reading.timestamp as u64
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_request() {
+        let request = write_request("http://localhost:8086/", "home/power", Some("my org&co"), Some("secret"));
+        assert_eq!(
+            request.url(),
+            "http://localhost:8086/api/v2/write?bucket=home%2Fpower&precision=s&org=my+org%26co"
+        );
+        assert_eq!(request.header("Authorization"), Some("Token secret"));
+
+        let request = write_request("http://localhost:8086", "power", None, None);
+        assert_eq!(request.url(), "http://localhost:8086/api/v2/write?bucket=power&precision=s");
+    }
+
+    #[test]
+    fn test_line_protocol() {
+        let reading = Reading {
+            timestamp: 1700000000.5,
+            instantaneous_watt: 500.0,
+            cumulative_kwh: 1234.5,
+            cumulative_reverse_kwh: 0.0,
+        };
+        assert_eq!(
+            line_protocol(&reading),
+            "smartmeter instantaneous=500,cumulative=1234.5,cumulative_reverse=0 1700000000"
+        );
+    }
+}
@@ -0,0 +1,14 @@
+// The protocol of the Wi-SUN module (SKSTACK IP) and ECHONET Lite, shared with the exporter binary.
+// `SmartMeterClient` is a blocking client on top of them for the other tools reading a smartmeter,
+// and `AsyncSmartMeterClient` is the one for tokio with the `async` feature.
+#[cfg(feature = "async")]
+pub mod async_client;
+pub mod client;
+pub mod command;
+pub mod echonet_lite;
+pub mod parser;
+pub mod response_buffer;
+
+pub use client::SmartMeterClient;
+#[cfg(feature = "async")]
+pub use async_client::AsyncSmartMeterClient;
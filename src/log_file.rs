@@ -0,0 +1,70 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// The log file written when RUST_LOG_DESTINATION=file, which is reopened on the next write after SIGHUP
+// so that logrotate can move the file away without copytruncate.
+pub struct LogFile {
+    path: String,
+    file: File,
+    reopen: Arc<AtomicBool>,
+}
+
+impl LogFile {
+    pub fn open(path: &str, reopen: Arc<AtomicBool>) -> io::Result<LogFile> {
+        Ok(LogFile {
+            path: path.to_string(),
+            file: open_append(path)?,
+            reopen,
+        })
+    }
+}
+
+fn open_append(path: &str) -> io::Result<File> {
+    OpenOptions::new().append(true).create(true).open(path)
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.reopen.swap(false, Ordering::Relaxed) {
+            // keep writing to the old file if the new one can not be opened, rather than losing the logs
+            match open_append(&self.path) {
+                Ok(file) => self.file = file,
+                Err(e) => eprintln!("failed to reopen log file {:?}: {:?}", self.path, e),
+            }
+        }
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reopen() {
+        let path = std::env::temp_dir().join(format!("log-file-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let rotated = format!("{}.1", path);
+        let reopen = Arc::new(AtomicBool::new(false));
+
+        let mut log = LogFile::open(path, Arc::clone(&reopen)).unwrap();
+        log.write_all(b"before\n").unwrap();
+        std::fs::rename(path, &rotated).unwrap();
+        log.write_all(b"rotated\n").unwrap();
+        reopen.store(true, Ordering::Relaxed);
+        log.write_all(b"after\n").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&rotated).unwrap(), "before\nrotated\n");
+        assert_eq!(std::fs::read_to_string(path).unwrap(), "after\n");
+        assert!(!reopen.load(Ordering::Relaxed));
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(&rotated).unwrap();
+    }
+}
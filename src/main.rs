@@ -1,48 +1,176 @@
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{Buf, Bytes};
 use log::{debug, error, info, warn};
-use serialport::{DataBits, SerialPort, StopBits, TTYPort};
+use serialport::{ClearBuffer, DataBits, SerialPort, StopBits, TTYPort};
 use std::error::Error;
-use std::fs::OpenOptions;
+use std::fmt;
 use std::io;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{channel, Receiver};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU16, AtomicU8, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, TrySendError};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 use std::thread::JoinHandle;
-use std::time::Duration;
-use std::{io::Read, io::Write, net::SocketAddr};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{io::Read, io::Write, net::Ipv6Addr, net::SocketAddr};
 
 use env_logger::{Builder, Env, Target};
-use prometheus_exporter::prometheus::register_gauge;
+use prometheus_exporter::prometheus::{Gauge, GaugeVec, Histogram, Registry};
+use sd_notify::NotifyState;
+use serde::Serialize;
+use signal_hook::consts::{SIGHUP, TERM_SIGNALS};
+use signal_hook::flag;
+use zeroize::{Zeroize, Zeroizing};
 
-mod parser;
-use parser::{parser, IpAddr, PanDesc};
-mod command;
-use command::Command;
-mod echonet_lite;
+mod args;
+use args::Args;
+use clap::Parser;
+use smartmeter_exporter::{command, echonet_lite, parser, response_buffer::ResponseBuffer};
+use parser::{Addr64, IpAddr, PanDesc};
+use command::{
+    scan_time, Command, SCAN_CHANNEL_MASK_ALL, SKSENDTO_SEC_ENCRYPTED, SKSENDTO_SEC_PLAIN, WOPT_BINARY,
+};
+mod error;
+use error::InitError;
+mod metrics;
+use metrics::{ConnectionState, Metrics, PhaseTimer, ReaderMetrics};
+mod mqtt;
+use mqtt::MqttPublisher;
+mod influx;
+use influx::InfluxWriter;
+mod capture;
+use capture::Capture;
+mod credentials;
+mod health;
+mod log_file;
+use log_file::LogFile;
+mod meter_label;
+use meter_label::MeterLabel;
+mod pandesc_cache;
+mod replay;
+use replay::ReplayFile;
+mod seen_tids;
+use seen_tids::SeenTids;
+mod serial_device;
+mod send_failures;
+use send_failures::SendFailures;
+mod smoothing;
+use smoothing::ExponentialMovingAverage;
 
 use crate::echonet_lite::{
-    EData, EDataFormat1, EDataProperty, EchonetLite, EpcLowVoltageSmartMeter,
-    EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+    cumulative_energy_increase, cumulative_energy_raw_unit, cumulative_energy_unit, fault_status, fixed_time_cumulative_energy, installation_location, instantaneous_current, instantaneous_energy, instantaneous_power_per_phase, fixed_time_unix, is_cumulative_energy_rollover, manufacturer,
+    operation_status, parse_historical_cumulative_energy, product_string,
+    EData, EDataFormat1, EDataProperty, EchonetLite, EpcLowVoltageSmartMeter, Esv,
+    Eoj, HistoricalCumulativeEnergy, PropertyMap, EOJ_HOUSING_LOW_VOLTAGE_SMART_METER, EOJ_MANAGEMENT_CONTROLLER,
 };
 use crate::parser::Response;
 
+// the serial device connected to the Wi-SUN module, which is replaced with a fake in tests
+trait SerialDevice: Read + Write + Send + Sized + 'static {
+    // another handle to the same device, used by the reader thread
+    fn try_clone_device(&self) -> io::Result<Self>;
+}
+
+impl SerialDevice for TTYPort {
+    fn try_clone_device(&self) -> io::Result<Self> {
+        Ok(self.try_clone_native()?)
+    }
+}
+
+// the serial port, or the recorded capture of it when REPLAY_FILE is set
+#[derive(Debug)]
+enum Uart {
+    Tty(TTYPort),
+    Replay(ReplayFile),
+}
+
+impl SerialDevice for Uart {
+    fn try_clone_device(&self) -> io::Result<Self> {
+        match self {
+            Uart::Tty(tty) => Ok(Uart::Tty(tty.try_clone_device()?)),
+            Uart::Replay(replay) => Ok(Uart::Replay(replay.try_clone_device()?)),
+        }
+    }
+}
+
+impl Read for Uart {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Uart::Tty(tty) => tty.read(buf),
+            Uart::Replay(replay) => replay.read(buf),
+        }
+    }
+}
+
+impl Write for Uart {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Uart::Tty(tty) => tty.write(buf),
+            Uart::Replay(replay) => replay.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Uart::Tty(tty) => tty.flush(),
+            Uart::Replay(replay) => replay.flush(),
+        }
+    }
+}
+
 #[derive(Debug)]
-struct UartReader {
-    inner: TTYPort,
+struct UartReader<D: SerialDevice> {
+    inner: D,
     is_closed: Arc<AtomicBool>,
 }
 
 #[derive(Debug)]
-struct UartWriter {
-    inner: TTYPort,
+struct UartWriter<D: SerialDevice> {
+    inner: D,
     is_closed: Arc<AtomicBool>,
 }
 
-fn split_uart(uart: TTYPort) -> (UartReader, UartWriter) {
+#[derive(Debug, Clone, Copy)]
+struct MeterInfo {
+    // multiplier in kWh decoded from EPC 0xE1
+    cumulative_energy_unit: f64,
+    // EPC 0xD3, 1 if the meter does not support it
+    coefficient: u32,
+    // EPC 0xD7, the cumulative energy counter wraps around at 10^digits
+    digits: Option<u8>,
+    // the object which replied the initial request, frames from the other instances are rejected
+    eoj: Eoj,
+    // EPC 0x9F, None if the meter does not advertise it
+    get_property_map: Option<PropertyMap>,
+    // PANA session lifetime read from the module, None if the firmware does not expose it
+    session_lifetime: Option<Duration>,
+}
+
+impl MeterInfo {
+    // properties which are not advertised by the meter would only be answered with SNA
+    fn energy_request_epcs(&self, requested: &[u8]) -> Vec<u8> {
+        requested
+            .iter()
+            .copied()
+            .filter(|&epc| self.get_property_map.is_none_or(|map| map.contains(epc)))
+            .collect()
+    }
+
+    // the reverse direction is only measured by meters with solar power, which advertise EPC 0xE4
+    fn historical_energy_epcs(&self) -> Vec<u8> {
+        let mut epcs = vec![EpcLowVoltageSmartMeter::HISTORICAL_CUMULATIVE_ENERGY_NORMAL_DIRECTION];
+        if self.get_property_map.is_some_and(|map| {
+            map.contains(EpcLowVoltageSmartMeter::HISTORICAL_CUMULATIVE_ENERGY_REVERSE_DIRECTION)
+        }) {
+            epcs.push(EpcLowVoltageSmartMeter::HISTORICAL_CUMULATIVE_ENERGY_REVERSE_DIRECTION);
+        }
+        epcs
+    }
+}
+
+fn split_uart<D: SerialDevice>(uart: D) -> (UartReader<D>, UartWriter<D>) {
     let is_closed = Arc::new(AtomicBool::new(false));
     (
         UartReader {
-            inner: uart.try_clone_native().unwrap(),
+            inner: uart.try_clone_device().unwrap(),
             is_closed: is_closed.clone(),
         },
         UartWriter {
@@ -52,7 +180,7 @@ fn split_uart(uart: TTYPort) -> (UartReader, UartWriter) {
     )
 }
 
-impl Read for UartReader {
+impl<D: SerialDevice> Read for UartReader<D> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if self.is_closed.load(Ordering::Acquire) {
             return Err(io::Error::new(
@@ -64,23 +192,33 @@ impl Read for UartReader {
     }
 }
 
-impl Drop for UartReader {
+impl<D: SerialDevice> Drop for UartReader<D> {
     fn drop(&mut self) {
         self.is_closed.store(true, Ordering::Release);
     }
 }
 
-impl UartWriter {
+impl<D: SerialDevice> UartWriter<D> {
     fn send_command(&mut self, cmd: Command) -> Result<(), Box<dyn Error>> {
-        debug!("sending command: {:?}", cmd);
+        let secret = matches!(cmd, Command::SkSetPwd { .. });
+        // Display masks the credentials
+        debug!("sending command: {}", cmd);
 
         let cmd: Bytes = cmd.into();
-        self.write_all(&cmd)?;
+        let result = self.write_all(&cmd);
+        if secret {
+            // the command is built into a BytesMut of its own, so it is not shared
+            match cmd.try_into_mut() {
+                Ok(mut cmd) => cmd[..].zeroize(),
+                Err(_) => warn!("can not wipe the password in the sent command"),
+            }
+        }
+        result?;
         Ok(())
     }
 }
 
-impl Write for UartWriter {
+impl<D: SerialDevice> Write for UartWriter<D> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         if self.is_closed.load(Ordering::Acquire) {
             return Err(io::Error::new(
@@ -105,53 +243,427 @@ impl Write for UartWriter {
     }
 }
 
-impl Drop for UartWriter {
+impl<D: SerialDevice> Drop for UartWriter<D> {
     fn drop(&mut self) {
         self.is_closed.store(true, Ordering::Release);
     }
 }
 
-fn active_scan(
-    sensor: &mut UartWriter,
+// the environment variable `name` parsed as T, or `default` if it is unset or invalid
+fn env_parse<T: FromStr + fmt::Debug>(name: &str, default: T) -> T {
+    env_parse_valid(name, default, |_| true)
+}
+
+// like env_parse, and the value rejected by `valid` falls back to `default` as well
+fn env_parse_valid<T: FromStr + fmt::Debug>(name: &str, default: T, valid: impl Fn(&T) -> bool) -> T {
+    parse_or(name, std::env::var(name).ok().as_deref(), default, valid)
+}
+
+// `raw` of the setting `name` parsed as T, or `default` if it is unset or invalid
+fn parse_or<T: FromStr + fmt::Debug>(name: &str, raw: Option<&str>, default: T, valid: impl Fn(&T) -> bool) -> T {
+    parse_with(name, raw, default, |raw| raw.parse::<T>().ok().filter(|value| valid(value)))
+}
+
+// like parse_or, for the values which are not parsed by FromStr such as hex and lists
+fn parse_with<T: fmt::Debug>(name: &str, raw: Option<&str>, default: T, parse: impl Fn(&str) -> Option<T>) -> T {
+    match raw {
+        Some(raw) => match parse(raw) {
+            Some(value) => value,
+            None => {
+                warn!("invalid {} {:?}, use {:?} instead", name, raw, default);
+                default
+            }
+        },
+        None => default,
+    }
+}
+
+// `raw` of the setting `name` parsed as T, or None if it is unset.
+// For the settings without a safe default, an invalid value is an error instead.
+fn parse_required<T>(name: &str, raw: Option<&str>) -> Result<Option<T>, Box<dyn Error>>
+where
+    T: FromStr,
+    T::Err: Error + 'static,
+{
+    match raw {
+        Some(raw) => match raw.parse::<T>() {
+            Ok(value) => Ok(Some(value)),
+            Err(e) => {
+                error!("invalid {}: {:?}", name, raw);
+                Err(e.into())
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+fn scan_max_attempts() -> u8 {
+    env_parse_valid("SCAN_MAX_ATTEMPTS", DEFAULT_SCAN_MAX_ATTEMPTS, |attempts| *attempts > 0)
+}
+
+// the channels to scan in hex, where bit n selects channel 33 + n
+fn scan_channel_mask() -> u32 {
+    parse_with(
+        "SCAN_CHANNEL_MASK",
+        std::env::var("SCAN_CHANNEL_MASK").ok().as_deref(),
+        SCAN_CHANNEL_MASK_ALL,
+        |raw| u32::from_str_radix(raw.trim_start_matches("0x"), 16).ok().filter(|mask| *mask != 0),
+    )
+}
+
+// the PAN to join without scanning, which is configured by WISUN_CHANNEL, WISUN_PAN_ID and WISUN_ADDR64
+fn configured_pan_desc() -> Option<PanDesc> {
+    let vars = (
+        std::env::var("WISUN_CHANNEL"),
+        std::env::var("WISUN_PAN_ID"),
+        std::env::var("WISUN_ADDR64"),
+    );
+    match vars {
+        (Ok(channel), Ok(pan_id), Ok(addr64)) => {
+            let pan_desc = parse_pan_desc(&channel, &pan_id, &addr64);
+            if pan_desc.is_none() {
+                warn!(
+                    "invalid WISUN_CHANNEL {:?}, WISUN_PAN_ID {:?} or WISUN_ADDR64 {:?}, scan instead",
+                    channel, pan_id, addr64
+                );
+            }
+            pan_desc
+        }
+        (Err(_), Err(_), Err(_)) => None,
+        _ => {
+            warn!("WISUN_CHANNEL, WISUN_PAN_ID and WISUN_ADDR64 must be set together, scan instead");
+            None
+        }
+    }
+}
+
+// the controller object the requests are sent from, e.g. CONTROLLER_EOJ=05FF02.
+// The meter replies to it as DEOJ, while the responses are matched by SEOJ and TID, so any instance works.
+fn controller_eoj() -> Eoj {
+    static CONTROLLER_EOJ: OnceLock<Eoj> = OnceLock::new();
+    *CONTROLLER_EOJ.get_or_init(|| {
+        let Ok(raw) = std::env::var("CONTROLLER_EOJ") else {
+            return EOJ_MANAGEMENT_CONTROLLER;
+        };
+        parse_eoj(&raw).unwrap_or_else(|| {
+            warn!("invalid CONTROLLER_EOJ {:?}, use {:?} instead", raw, EOJ_MANAGEMENT_CONTROLLER);
+            EOJ_MANAGEMENT_CONTROLLER
+        })
+    })
+}
+
+// 6 hex digits of class group, class and instance code
+fn parse_eoj(raw: &str) -> Option<Eoj> {
+    let raw = raw.trim_start_matches("0x");
+    if raw.len() != 6 {
+        return None;
+    }
+    let eoj = u32::from_str_radix(raw, 16).ok()?;
+    Some(Eoj {
+        class_group_code: (eoj >> 16) as u8,
+        class_code: (eoj >> 8) as u8,
+        instance_code: eoj as u8,
+    })
+}
+
+// all in hex as shown in EPANDESC, e.g. "21", "8888" and "001D129012345678"
+fn parse_pan_desc(channel: &str, pan_id: &str, addr64: &str) -> Option<PanDesc> {
+    let channel = u8::from_str_radix(channel.trim_start_matches("0x"), 16).ok()?;
+    let pan_id = u16::from_str_radix(pan_id.trim_start_matches("0x"), 16).ok()?;
+    if addr64.len() != 16 || u64::from_str_radix(addr64, 16).is_err() {
+        return None;
+    }
+    Some(PanDesc {
+        channel,
+        pan_id,
+        addr: addr64.to_ascii_uppercase(),
+        ..Default::default()
+    })
+}
+
+// retry the scan with escalating duration since the meter is frequently not found on the first scan in noisy environment
+fn active_scan<D: SerialDevice>(
+    sensor: &mut UartWriter<D>,
+    receiver: &mut Receiver<Response>,
+    timeout: Duration,
+) -> Result<PanDesc, InitError> {
+    let max_attempts = scan_max_attempts();
+    let channel_mask = scan_channel_mask();
+    // whether every scan completed with EVENT 0x22 without finding any PAN
+    let mut empty = true;
+    for attempt in 0..max_attempts {
+        let duration = SCAN_MIN_DURATION
+            .saturating_add(attempt)
+            .min(SCAN_MAX_DURATION);
+        match active_scan_once(sensor, receiver, channel_mask, duration, timeout) {
+            Ok(Some(pandesc)) => return Ok(pandesc),
+            Ok(None) => {}
+            Err(e @ InitError::CommandTimeout { .. }) => {
+                warn!("scan with duration {} did not complete: {}", duration, e);
+                empty = false;
+            }
+            Err(e) => return Err(e),
+        }
+        info!(
+            "unable to find sensor with scan duration {} ({}/{})",
+            duration,
+            attempt + 1,
+            max_attempts
+        );
+    }
+
+    if empty {
+        Err(InitError::ScanEmpty)
+    } else {
+        Err(InitError::ScanFailed)
+    }
+}
+
+fn active_scan_once<D: SerialDevice>(
+    sensor: &mut UartWriter<D>,
     receiver: &mut Receiver<Response>,
-) -> Result<PanDesc, Box<dyn Error>> {
-    sensor.send_command(Command::ActiveScan { duration: 6 })?;
-    let r = receiver.recv()?;
+    channel_mask: u32,
+    duration: u8,
+    timeout: Duration,
+) -> Result<Option<PanDesc>, InitError> {
+    sensor.send_command(Command::ActiveScan {
+        channel_mask,
+        duration,
+    })?;
+    let r = recv_response_timeout(receiver, timeout, "SKSCAN")?;
     if !matches!(r, Response::SkScan { .. }) {
-        return Err("SKSCAN failed".into());
+        return Err(InitError::CommandFailed("SKSCAN"));
     }
 
-    let mut tmp = Err("unable to find sensor within duration".into());
+    // some firmware never reports EVENT 0x22, so give up after the scan should have finished
+    let scan_timeout = scan_time(channel_mask, duration) + timeout;
+    let deadline = Instant::now() + scan_timeout;
+    let mut tmp = None;
     loop {
-        let r = receiver.recv()?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let r = match receiver.recv_timeout(remaining) {
+            Ok(r) => r,
+            Err(RecvTimeoutError::Timeout) => {
+                warn!("EVENT 0x22 did not arrive in {:?}", scan_timeout);
+                return match tmp {
+                    Some(pandesc) => Ok(Some(pandesc)),
+                    None => Err(InitError::CommandTimeout {
+                        command: "SKSCAN".to_string(),
+                        timeout: scan_timeout,
+                    }),
+                };
+            }
+            Err(e) => return Err(InitError::Disconnected(e.into())),
+        };
         match r {
-            Response::Event { num, sender, param } => {
-                if num == 0x22 {
-                    return tmp;
-                }
+            Response::Event { num: 0x22, .. } => {
+                return Ok(tmp);
             }
             Response::EPanDesc(pandesc) => {
-                tmp = Ok(pandesc);
+                tmp = Some(pandesc);
             }
             _ => {}
         }
     }
 }
 
+// wait for the reply to a command, `command` names the command in the error
+fn recv_response_timeout(
+    receiver: &mut Receiver<Response>,
+    timeout: Duration,
+    command: &str,
+) -> Result<Response, InitError> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(remaining) {
+            // the reader thread reports the idle UART, which is not the reply
+            Ok(Response::UartTimeOut) => {}
+            Ok(Response::Fail { code }) => {
+                warn!("{} failed with ER{:02}", command, code);
+                return Err(InitError::CommandRejected {
+                    command: command.to_string(),
+                    code,
+                });
+            }
+            Ok(r) => return Ok(r),
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(InitError::CommandTimeout {
+                    command: command.to_string(),
+                    timeout,
+                });
+            }
+            Err(e) => return Err(InitError::Disconnected(e.into())),
+        }
+    }
+}
+
+fn cmd_timeout() -> Duration {
+    Duration::from_millis(env_parse_valid("CMD_TIMEOUT_MS", DEFAULT_CMD_TIMEOUT_MS, |ms| *ms > 0))
+}
+
+// # of consecutive poll cycles without instantaneous energy, after which the session is reconnected
+fn max_empty_cycles() -> u32 {
+    env_parse_valid("MAX_EMPTY_CYCLES", DEFAULT_MAX_EMPTY_CYCLES, |cycles| *cycles > 0)
+}
+
+// the lifetime assumed when the module does not tell the PANA session lifetime
+fn pana_session_lifetime() -> Duration {
+    Duration::from_secs(env_parse_valid(
+        "PANA_SESSION_LIFETIME_SECS",
+        DEFAULT_PANA_SESSION_LIFETIME.as_secs(),
+        |secs| *secs > 0,
+    ))
+}
+
+// the session is reconnected this long before it expires, instead of waiting for EVENT 0x29. 0 disables it.
+fn pana_renew_before() -> Duration {
+    Duration::from_secs(env_parse("PANA_RENEW_BEFORE_SECS", DEFAULT_PANA_RENEW_BEFORE.as_secs()))
+}
+
+// # of resends of the cumulative energy unit request during the initialization
+fn unit_request_max_resends() -> u32 {
+    env_parse("UNIT_REQUEST_MAX_RESENDS", DEFAULT_UNIT_REQUEST_MAX_RESENDS)
+}
+
+// # of responses the reader thread keeps for the main loop, further ones are dropped
+fn reader_channel_bound() -> usize {
+    env_parse_valid("READER_CHANNEL_BOUND", DEFAULT_READER_CHANNEL_BOUND, |bound| *bound > 0)
+}
+
+// # of EVENT 0x21 send failures within CHANNEL_RESCAN_WINDOW_SECS to scan again, 0 disables it
+fn channel_rescan_threshold() -> usize {
+    env_parse("CHANNEL_RESCAN_THRESHOLD", DEFAULT_CHANNEL_RESCAN_THRESHOLD)
+}
+
+fn channel_rescan_window() -> Duration {
+    Duration::from_secs(env_parse_valid(
+        "CHANNEL_RESCAN_WINDOW_SECS",
+        DEFAULT_CHANNEL_RESCAN_WINDOW.as_secs(),
+        |secs| *secs > 0,
+    ))
+}
+
+// EPCs requested on each poll instead of ENERGY_REQUEST_EPCS, e.g. POLL_EPCS=E7,EA
+fn poll_epcs() -> Option<Vec<u8>> {
+    let raw = std::env::var("POLL_EPCS").ok()?;
+    let epcs = parse_epcs(&raw);
+    if epcs.is_none() {
+        warn!(
+            "invalid POLL_EPCS {:?}, which must be some of {:X?}, use the default instead",
+            raw, ENERGY_REQUEST_EPCS
+        );
+    }
+    epcs
+}
+
+// only the EPCs of ENERGY_REQUEST_EPCS are accepted since the others are not exported
+fn parse_epcs(raw: &str) -> Option<Vec<u8>> {
+    let epcs = raw
+        .split(',')
+        .map(|epc| u8::from_str_radix(epc.trim().trim_start_matches("0x"), 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    if epcs.is_empty() || !epcs.iter().all(|epc| ENERGY_REQUEST_EPCS.contains(epc)) {
+        return None;
+    }
+    Some(epcs)
+}
+
+// the fixed-time cumulative energy is only updated every 30 minutes by the meter
+fn cumulative_interval() -> Duration {
+    Duration::from_secs(env_parse("CUMULATIVE_INTERVAL_SECS", DEFAULT_CUMULATIVE_INTERVAL_SECS))
+}
+
+// the EPCs requested in a poll, the cumulative energy is left out until it is due.
+// The gauges keep the last value in between.
+fn cycle_epcs(epcs: &[u8], cumulative_due: bool) -> Vec<u8> {
+    epcs.iter()
+        .copied()
+        .filter(|epc| cumulative_due || !CUMULATIVE_ENERGY_EPCS.contains(epc))
+        .collect()
+}
+
+// the weight of the latest reading in instantaneous_energy_smoothed, which is disabled if unset
+fn smoothing_alpha() -> Option<f64> {
+    let raw = std::env::var("INSTANTANEOUS_ENERGY_SMOOTHING").ok()?;
+    match raw.parse::<f64>() {
+        Ok(alpha) if alpha > 0.0 && alpha <= 1.0 => Some(alpha),
+        _ => {
+            warn!(
+                "invalid INSTANTANEOUS_ENERGY_SMOOTHING {:?}, smoothing is disabled",
+                raw
+            );
+            None
+        }
+    }
+}
+
+// bucket boundaries of instantaneous_energy_watts, which is disabled unless either variable is set
+fn energy_histogram_buckets() -> Option<Vec<f64>> {
+    match std::env::var("INSTANTANEOUS_ENERGY_BUCKETS") {
+        Ok(raw) => Some(parse_with(
+            "INSTANTANEOUS_ENERGY_BUCKETS",
+            Some(&raw),
+            DEFAULT_ENERGY_HISTOGRAM_BUCKETS.to_vec(),
+            parse_buckets,
+        )),
+        Err(_) => matches!(std::env::var("INSTANTANEOUS_ENERGY_HISTOGRAM").as_deref(), Ok("1"))
+            .then(|| DEFAULT_ENERGY_HISTOGRAM_BUCKETS.to_vec()),
+    }
+}
+
+// comma separated upper bounds in Watt, which must be increasing
+fn parse_buckets(raw: &str) -> Option<Vec<f64>> {
+    let buckets = raw
+        .split(',')
+        .map(|bucket| bucket.trim().parse::<f64>().ok().filter(|b| b.is_finite()))
+        .collect::<Option<Vec<f64>>>()?;
+    if buckets.is_empty() || !buckets.windows(2).all(|w| w[0] < w[1]) {
+        return None;
+    }
+    Some(buckets)
+}
+
+// SEC of SKSENDTO for the requests to the smartmeter, only for test setups which do not encrypt
+fn sksendto_sec() -> u8 {
+    match std::env::var("SKSENDTO_SEC").as_deref() {
+        Ok("0") => SKSENDTO_SEC_PLAIN,
+        Ok("1") | Err(_) => SKSENDTO_SEC_ENCRYPTED,
+        Ok(raw) => {
+            warn!("invalid SKSENDTO_SEC {:?}, encrypt instead", raw);
+            SKSENDTO_SEC_ENCRYPTED
+        }
+    }
+}
+
+// some firmware fails SKJOIN intermittently unless the meter is in the neighbor cache beforehand
+fn add_neighbor() -> bool {
+    matches!(std::env::var("SKADDNBR").as_deref(), Ok("1"))
+}
+
 fn wait_for_connect(
-    sensor: &mut UartWriter,
     receiver: &mut Receiver<Response>,
-) -> Result<(), Box<dyn Error>> {
-    let total_wait_time = Duration::from_millis(0);
+    timeout: Duration,
+) -> Result<(), InitError> {
+    let deadline = Instant::now() + timeout;
     loop {
-        if total_wait_time > Duration::from_secs(30) {
-            return Err("connect timeout".into());
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(InitError::JoinTimeout);
         }
 
-        let r = receiver.recv()?;
+        let r = match receiver.recv_timeout(remaining) {
+            Ok(r) => r,
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(InitError::JoinTimeout);
+            }
+            Err(e) => {
+                return Err(InitError::Disconnected(e.into()));
+            }
+        };
         match r {
             Response::Event { num: 0x24, .. } => {
-                return Err("failed to connect to PANA".into());
+                return Err(InitError::JoinFailed);
             }
             Response::Event { num: 0x25, .. } => {
                 return Ok(());
@@ -161,104 +673,511 @@ fn wait_for_connect(
     }
 }
 
-fn send_initialize_command_sequence(
-    writer: &mut UartWriter,
+// wait for the reply of SKINFO, skipping other events which may arrive just after joining
+// the reply of SKINFO
+struct ModuleInfo {
+    ipaddr: IpAddr,
+    addr64: Addr64,
+    channel: u8,
+    pan_id: u16,
+}
+
+fn wait_for_info(
     receiver: &mut Receiver<Response>,
-) -> Result<(IpAddr, f64), Box<dyn Error>> {
-    // reset
-    writer.send_command(Command::SkReset)?;
-    let r = receiver.recv()?;
-    if !matches!(r, Response::SkReset) {
-        return Err("SKRESET failed".into());
+    timeout: Duration,
+) -> Result<ModuleInfo, Box<dyn Error>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err("SKINFO timeout".into());
+        }
+
+        match receiver.recv_timeout(remaining) {
+            Ok(Response::SkInfo {
+                ipaddr,
+                addr64,
+                channel,
+                pan_id,
+            }) => {
+                return Ok(ModuleInfo {
+                    ipaddr,
+                    addr64,
+                    channel,
+                    pan_id,
+                })
+            }
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                return Err("SKINFO timeout".into());
+            }
+            Err(e) => {
+                return Err(e.into());
+            }
+        }
     }
+}
 
-    // send id
-    writer.send_command(Command::SkSetRbid { id: B_ID })?;
-    let r = receiver.recv()?;
+// terminate the PANA session and wait until the module reports the session is closed.
+// FAIL (ER10 without a session) means there is nothing to terminate.
+fn terminate<D: SerialDevice>(
+    writer: &mut UartWriter<D>,
+    receiver: &mut Receiver<Response>,
+) -> Result<(), Box<dyn Error>> {
+    writer.send_command(Command::SkTerm)?;
 
-    if !matches!(r, Response::SkSetRbid { .. }) {
-        return Err("SKSETRBID failed".into());
+    let deadline = Instant::now() + TERMINATE_TIMEOUT;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match recv_response_timeout(receiver, remaining, "SKTERM") {
+            Ok(Response::Event { num: 0x27, .. }) => {
+                return Ok(());
+            }
+            Ok(Response::Event { num: 0x28, .. }) => {
+                return Err("no response from PANA for termination request".into());
+            }
+            Ok(_) => {}
+            Err(InitError::CommandRejected { .. }) => {
+                debug!("no PANA session to terminate");
+                return Ok(());
+            }
+            Err(InitError::CommandTimeout { .. }) => {
+                return Err("terminate timeout".into());
+            }
+            Err(e) => return Err(e.into()),
+        }
     }
+}
 
-    // send pw
-    writer.send_command(Command::SkSetPwd { pwd: B_PW })?;
-    let r = receiver.recv()?;
-    if !matches!(r, Response::SkSetPwd { .. }) {
-        return Err("SKSETPWD failed".into());
+// the link-local IPv6 address derived from the MAC address, which is what SKLL64 returns
+fn link_local_addr(addr64: &str) -> Option<IpAddr> {
+    if addr64.len() != 16 {
+        return None;
     }
+    // flip the universal/local bit of the interface identifier
+    let id = u64::from_str_radix(addr64, 16).ok()? ^ 0x0200_0000_0000_0000;
+    Some(IpAddr::from(Ipv6Addr::from((0xFE80u128 << 112) | id as u128)))
+}
 
-    let pan_desc = active_scan(writer, receiver)?;
-    debug!("pan_desc: {:?}", pan_desc);
+// The reply of SKLL64 differs between modules and settings: BP35A1 echoes back the command before the address,
+// while modules with echo back disabled only reply the address. If neither is returned, derive the address locally.
+fn resolve_link_local_addr<D: SerialDevice>(
+    writer: &mut UartWriter<D>,
+    receiver: &mut Receiver<Response>,
+    addr64: &str,
+    timeout: Duration,
+) -> Result<IpAddr, InitError> {
+    writer.send_command(Command::SkLl64 { addr64 })?;
+    let r = recv_response_timeout(receiver, timeout, "SKLL64");
+    match r {
+        Ok(Response::SkLl64 { ipaddr, .. }) | Ok(Response::LinkLocalAddr { ipaddr }) => Ok(ipaddr),
+        _ => {
+            warn!("unexpected reply of SKLL64 {:?}, derive the address from {}", r, addr64);
+            link_local_addr(addr64).ok_or(InitError::CommandFailed("SKLL64"))
+        }
+    }
+}
 
+// join the PAN described by pan_desc and wait until the PANA session is established
+fn join<D: SerialDevice>(
+    writer: &mut UartWriter<D>,
+    receiver: &mut Receiver<Response>,
+    pan_desc: &PanDesc,
+    timeout: Duration,
+    add_neighbor: bool,
+    counter_join_recovery: &Gauge,
+    init_phase_seconds: &GaugeVec,
+) -> Result<IpAddr, InitError> {
+    let phase = PhaseTimer::start(init_phase_seconds, "sreg");
     // set channel
     writer.send_command(Command::SkSreg {
         sreg: 0x02,
-        val: pan_desc.channel as u32,
+        val: Some(pan_desc.channel as u32),
     })?;
-    let r = receiver.recv()?;
+    let r = recv_response_timeout(receiver, timeout, "SKSREG")?;
     if !matches!(r, Response::SkSreg { .. }) {
-        return Err("SKSREG failed".into());
+        return Err(InitError::CommandFailed("SKSREG"));
+    }
+
+    // some modules reply OK even if they reject the write, so read the channel back
+    writer.send_command(Command::SkSreg {
+        sreg: 0x02,
+        val: None,
+    })?;
+    match recv_response_timeout(receiver, timeout, "SKSREG")? {
+        Response::EsReg { val, .. } if val == pan_desc.channel as u32 => {}
+        Response::EsReg { sreg, val } => {
+            return Err(InitError::RegisterMismatch {
+                sreg,
+                expected: pan_desc.channel as u32,
+                actual: val,
+            });
+        }
+        _ => return Err(InitError::CommandFailed("SKSREG")),
     }
 
     // set pan id
     writer.send_command(Command::SkSreg {
         sreg: 0x03,
-        val: pan_desc.pan_id as u32,
+        val: Some(pan_desc.pan_id as u32),
     })?;
-    let r = receiver.recv()?;
+    let r = recv_response_timeout(receiver, timeout, "SKSREG")?;
     if !matches!(r, Response::SkSreg { .. }) {
-        return Err("SKSREG failed".into());
+        return Err(InitError::CommandFailed("SKSREG"));
     }
 
-    // convert addr
-    writer.send_command(Command::SkLl64 {
-        addr64: &pan_desc.addr,
-    })?;
-    let r = receiver.recv()?;
-    let ipv6_addr = match r {
-        Response::SkLl64 { ipaddr, .. } => ipaddr,
-        _ => {
-            return Err("SKLL64 failed".into());
+    drop(phase);
+
+    let phase = PhaseTimer::start(init_phase_seconds, "ll64");
+    let ipv6_addr = resolve_link_local_addr(writer, receiver, &pan_desc.addr, timeout)?;
+    drop(phase);
+
+    let _phase = PhaseTimer::start(init_phase_seconds, "join");
+    if add_neighbor {
+        writer.send_command(Command::SkAddNbr {
+            ipaddr: &ipv6_addr,
+            addr64: &pan_desc.addr,
+        })?;
+        let r = recv_response_timeout(receiver, timeout, "SKADDNBR")?;
+        if !matches!(r, Response::SkAddNbr { .. }) {
+            return Err(InitError::CommandFailed("SKADDNBR"));
         }
-    };
+    }
+
+    match connect(writer, receiver, &ipv6_addr, timeout) {
+        Err(InitError::JoinFailed) => {
+            // EVENT 0x24 is often caused by the stale session the meter still keeps,
+            // so terminate it and retry once before scanning again
+            warn!("failed to connect to PANA, terminate the session and retry");
+            if let Err(e) = terminate(writer, receiver) {
+                warn!("failed to terminate the PANA session: {:?}", e);
+            }
+            connect(writer, receiver, &ipv6_addr, timeout)?;
+            counter_join_recovery.inc();
+        }
+        r => r?,
+    }
+
+    Ok(ipv6_addr)
+}
 
-    // connect to pana
-    writer.send_command(Command::SkJoin { ipaddr: &ipv6_addr })?;
-    let r = receiver.recv()?;
+// connect to pana
+fn connect<D: SerialDevice>(
+    writer: &mut UartWriter<D>,
+    receiver: &mut Receiver<Response>,
+    ipv6_addr: &IpAddr,
+    timeout: Duration,
+) -> Result<(), InitError> {
+    writer.send_command(Command::SkJoin { ipaddr: ipv6_addr })?;
+    let r = recv_response_timeout(receiver, timeout, "SKJOIN")?;
     if !matches!(r, Response::SkJoin { .. }) {
-        return Err("SKJOIN failed".into());
+        return Err(InitError::CommandFailed("SKJOIN"));
+    }
+
+    wait_for_connect(receiver, CONNECT_TIMEOUT)
+}
+
+fn send_initialize_command_sequence<D: SerialDevice>(
+    writer: &mut UartWriter<D>,
+    receiver: &mut Receiver<Response>,
+    b_id: &str,
+    b_pw: &str,
+    metrics: &Metrics,
+) -> Result<(IpAddr, MeterInfo), InitError> {
+    let timeout = cmd_timeout();
+
+    // reset
+    let phase = PhaseTimer::start(&metrics.init_phase_seconds, "reset");
+    writer.send_command(Command::SkReset)?;
+    let r = recv_response_timeout(receiver, timeout, "SKRESET")?;
+    if !matches!(r, Response::SkReset) {
+        return Err(InitError::ResetFailed);
+    }
+    drop(phase);
+
+    // firmware version, which is only informational, and some modules reply in another format
+    writer.send_command(Command::SkVer)?;
+    metrics.module_firmware_info.reset();
+    match recv_response_timeout(receiver, timeout, "SKVER") {
+        Ok(Response::SkVer { version }) => {
+            info!("module firmware version: {}", version);
+            metrics
+                .module_firmware_info
+                .with_label_values(&[version.as_str()])
+                .set(1.0);
+        }
+        Ok(r) => warn!("unexpected response to SKVER, firmware version is unknown: {}", r),
+        Err(e @ (InitError::CommandRejected { .. } | InitError::CommandTimeout { .. })) => {
+            warn!("firmware version is unknown: {}", e);
+        }
+        Err(e) => return Err(e),
+    }
+
+    // show ERXUDP payload in binary.
+    // WOPT is saved in the flash memory which has limited write cycles, so write it only if it differs.
+    // Older firmware has no ROPT, and WOPT is written without the readback then.
+    writer.send_command(Command::SkRopt)?;
+    let mode = match recv_response_timeout(receiver, timeout, "ROPT") {
+        Ok(Response::SkRopt { mode }) => Some(mode),
+        Ok(r) => {
+            warn!("unexpected response to ROPT, writing WOPT: {}", r);
+            None
+        }
+        Err(e @ InitError::CommandRejected { .. }) => {
+            warn!("ROPT is not supported, writing WOPT: {}", e);
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    if mode != Some(WOPT_BINARY) {
+        writer.send_command(Command::SkWopt { mode: WOPT_BINARY })?;
+        let r = recv_response_timeout(receiver, timeout, "WOPT")?;
+        if !matches!(r, Response::SkWopt { .. }) {
+            return Err(InitError::CommandFailed("WOPT"));
+        }
+    }
+
+    // send id
+    let phase = PhaseTimer::start(&metrics.init_phase_seconds, "rbid");
+    writer.send_command(Command::SkSetRbid { id: b_id })?;
+    let r = recv_response_timeout(receiver, timeout, "SKSETRBID")?;
+
+    if !matches!(r, Response::SkSetRbid { .. }) {
+        return Err(InitError::RbidRejected);
+    }
+    drop(phase);
+
+    // send pw
+    let phase = PhaseTimer::start(&metrics.init_phase_seconds, "pwd");
+    writer.send_command(Command::SkSetPwd { pwd: b_pw })?;
+    let r = recv_response_timeout(receiver, timeout, "SKSETPWD")?;
+    if !matches!(r, Response::SkSetPwd { .. }) {
+        return Err(InitError::PwdRejected);
+    }
+    drop(phase);
+
+    let add_neighbor = add_neighbor();
+    let cache = std::env::var("PANDESC_CACHE").ok();
+    let cached = match cache.as_deref().map(pandesc_cache::load) {
+        Some(Ok(cached)) => cached,
+        Some(Err(e)) => {
+            warn!("failed to load PAN descriptor cache: {:?}", e);
+            None
+        }
+        None => None,
+    };
+    // the configured or cached PAN is skipped once to leave a congested channel
+    let (configured, cached) = if FORCE_SCAN.swap(false, Ordering::Relaxed) {
+        info!("scanning for a less congested channel");
+        (None, None)
+    } else {
+        (configured_pan_desc(), cached)
+    };
+    let joined = match (configured, cached) {
+        // the configured PAN takes precedence over the cache, and the cache is not updated on failure
+        (Some(pan_desc), _) => {
+            info!("joining with configured PAN descriptor: {:?}", pan_desc);
+            ConnectionState::Joining.set(&metrics.connection_state);
+            match join(
+                writer,
+                receiver,
+                &pan_desc,
+                timeout,
+                add_neighbor,
+                &metrics.counter_join_recovery,
+                &metrics.init_phase_seconds,
+            ) {
+                Ok(ipv6_addr) => Some(ipv6_addr),
+                Err(e) => {
+                    warn!("failed to join with configured PAN descriptor, scanning: {:?}", e);
+                    None
+                }
+            }
+        }
+        (None, Some(pan_desc)) => {
+            info!("joining with cached PAN descriptor: {:?}", pan_desc);
+            ConnectionState::Joining.set(&metrics.connection_state);
+            match join(
+                writer,
+                receiver,
+                &pan_desc,
+                timeout,
+                add_neighbor,
+                &metrics.counter_join_recovery,
+                &metrics.init_phase_seconds,
+            ) {
+                Ok(ipv6_addr) => {
+                    PANDESC_CACHE_FAILURES.store(0, Ordering::Relaxed);
+                    Some(ipv6_addr)
+                }
+                Err(e) => {
+                    warn!("failed to join with cached PAN descriptor, scanning: {:?}", e);
+                    let failures = PANDESC_CACHE_FAILURES.fetch_add(1, Ordering::Relaxed) + 1;
+                    if failures >= PANDESC_CACHE_MAX_FAILURES {
+                        info!("invalidate PAN descriptor cache after {} failures", failures);
+                        PANDESC_CACHE_FAILURES.store(0, Ordering::Relaxed);
+                        if let Err(e) = pandesc_cache::remove(cache.as_deref().unwrap()) {
+                            warn!("failed to remove PAN descriptor cache: {:?}", e);
+                        }
+                    }
+                    None
+                }
+            }
+        }
+        (None, None) => None,
+    };
+
+    let ipv6_addr = match joined {
+        Some(ipv6_addr) => ipv6_addr,
+        None => {
+            ConnectionState::Scanning.set(&metrics.connection_state);
+            let phase = PhaseTimer::start(&metrics.init_phase_seconds, "scan");
+            let pan_desc = active_scan(writer, receiver, timeout)?;
+            drop(phase);
+            debug!("pan_desc: {:?}", pan_desc);
+            // ERXUDP of BP35A1 does not report LQI, so the signal strength is only updated on each scan
+            info!("signal strength of smartmeter: {:.1} dBm", pan_desc.rssi());
+            metrics.wisun_rssi.set(pan_desc.rssi());
+            // joining a neighbour's meter fails on PANA authentication anyway, but tell why
+            if !pan_desc.matches_b_id(b_id) {
+                warn!(
+                    "PairID {} of the smartmeter found does not match the B-route ID, it may be another meter",
+                    pan_desc.pair_id
+                );
+            }
+
+            ConnectionState::Joining.set(&metrics.connection_state);
+            let ipv6_addr = join(
+                writer,
+                receiver,
+                &pan_desc,
+                timeout,
+                add_neighbor,
+                &metrics.counter_join_recovery,
+                &metrics.init_phase_seconds,
+            )?;
+            if let Some(path) = &cache {
+                if let Err(e) = pandesc_cache::save(path, &pan_desc) {
+                    warn!("failed to save PAN descriptor cache: {:?}", e);
+                }
+            }
+            ipv6_addr
+        }
+    };
+
+    // which channel is negotiated is useful to diagnose interference, but not required to continue
+    writer.send_command(Command::SkInfo)?;
+    match wait_for_info(receiver, INFO_TIMEOUT) {
+        Ok(info) => {
+            info!(
+                "module info: ipaddr={}, addr64={}, channel={:#x}, pan_id={:#x}",
+                info.ipaddr, info.addr64, info.channel, info.pan_id
+            );
+            metrics.current_channel.set(info.channel as f64);
+            metrics.current_pan_id.set(info.pan_id as f64);
+        }
+        Err(e) => warn!("failed to get module info: {:?}", e),
     }
 
-    wait_for_connect(writer, receiver)?;
+    // not every firmware lets the lifetime be read, then it is approximated by PANA_SESSION_LIFETIME_SECS
+    writer.send_command(Command::SkSreg {
+        sreg: SREG_PANA_SESSION_LIFETIME,
+        val: None,
+    })?;
+    let session_lifetime = match recv_response_timeout(receiver, timeout, "SKSREG") {
+        Ok(Response::EsReg { val, .. }) => {
+            info!("PANA session lifetime: {}s", val);
+            Some(Duration::from_secs(val as u64))
+        }
+        r => {
+            debug!("PANA session lifetime is not available: {:?}", r);
+            None
+        }
+    };
 
-    writer.send_command(Command::SendCumulativeEnergyUnitRequeest { ipaddr: &ipv6_addr })?;
+    let phase = PhaseTimer::start(&metrics.init_phase_seconds, "unit-request");
+    let (sec, tid) = (sksendto_sec(), next_tid());
+    let request = || Command::SendGetRequest {
+        ipaddr: &ipv6_addr,
+        sec,
+        seoj: controller_eoj(),
+        tid,
+        epcs: INITIAL_REQUEST_EPCS,
+    };
+    writer.send_command(request())?;
+    // a single lost frame should not fail the whole initialization, so resend within UNIT_RESPONSE_TIMEOUT
+    let max_resends = unit_request_max_resends();
+    let resend_interval = UNIT_RESPONSE_TIMEOUT / (max_resends + 1);
+    let mut resends = 0;
+    let mut last_sent = Instant::now();
     let mut unit = 0.0;
-    let total_wait_time = std::time::Instant::now();
+    // meters which does not support EPC 0xD3 do not need the coefficient
+    let mut coefficient = 1;
+    let mut digits = None;
+    let mut eoj = EOJ_HOUSING_LOW_VOLTAGE_SMART_METER;
+    let mut get_property_map = None;
+    let mut product = None;
+    let mut serial = None;
+    let deadline = Instant::now() + UNIT_RESPONSE_TIMEOUT;
 
     'wait_response: loop {
-        if total_wait_time.elapsed() > Duration::from_secs(19) {
+        let now = Instant::now();
+        if now >= deadline {
             break 'wait_response;
         }
+        if resends < max_resends && now >= last_sent + resend_interval {
+            warn!("no response for cumulative energy unit request, resending");
+            writer.send_command(request())?;
+            resends += 1;
+            last_sent = Instant::now();
+            metrics.counter_unit_request_retry.inc();
+        }
 
-        let r = receiver.recv()?;
+        // wake up for the next resend even if nothing arrives
+        let wake = if resends < max_resends {
+            deadline.min(last_sent + resend_interval)
+        } else {
+            deadline
+        };
+        let r = match receiver.recv_timeout(wake.saturating_duration_since(Instant::now())) {
+            Ok(r) => r,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(e) => return Err(InitError::Disconnected(e.into())),
+        };
         match r {
-            Response::SkSendTo { result: 0x00, .. } => {}
-            Response::SkSendTo { result: _, .. } => {
-                return Err("Send cumulative energy unit request failed".into());
+            Response::SkSendTo { result: Some(UDP_SEND_SUCCESS) | None, .. } => {}
+            Response::SkSendTo { .. }
+            | Response::Event { num: 0x21, param: Some(UDP_SEND_FAILURE), .. }
+            | Response::Fail { .. } => {
+                warn!("failed to send cumulative energy unit request: {}", r);
+                if resends >= max_resends {
+                    return Err(InitError::SendFailed);
+                }
+                writer.send_command(request())?;
+                resends += 1;
+                last_sent = Instant::now();
+                metrics.counter_unit_request_retry.inc();
+            }
+            Response::ERxUdp { secured: 0x00, ref sender, .. } => {
+                warn!("ignore unsecured frame from {}", sender);
             }
             Response::ERxUdp {
                 data:
                     EchonetLite {
                         edata:
                             EData::EDataFormat1(EDataFormat1 {
-                                seoj: EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+                                seoj,
                                 props,
                                 ..
                             }),
                         ..
                     },
                 ..
-            } => {
+            } if seoj.is_same_class(&EOJ_HOUSING_LOW_VOLTAGE_SMART_METER) => {
+                if seoj != EOJ_HOUSING_LOW_VOLTAGE_SMART_METER {
+                    info!("smartmeter replied as {}", seoj);
+                }
+                eoj = seoj;
                 for prop in props {
                     match prop {
                         EDataProperty {
@@ -267,71 +1186,361 @@ fn send_initialize_command_sequence(
                             mut edt,
                             ..
                         } => {
-                            let unit_data = edt.get_u8();
-                            unit = match unit_data {
-                                0x0 => 1.0,
-                                0x1 => 0.1,
-                                0x2 => 0.01,
-                                0x3 => 0.001,
-                                0x4 => 0.0001,
-                                0xa => 10.0,
-                                0xb => 100.0,
-                                0xc => 1000.0,
-                                0xd => 10000.0,
-                                _ => {
-                                    return Err("Invalid cumulative energy unit".into());
+                            let raw = edt.get_u8();
+                            unit = cumulative_energy_unit(raw).ok_or(InitError::InvalidUnit(raw))?;
+                        }
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::COEFFICIENT,
+                            pdc: 0x04,
+                            mut edt,
+                            ..
+                        } => {
+                            coefficient = edt.get_u32();
+                        }
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::EFFECTIVE_DIGITS_OF_CUMULATIVE_ENERGY,
+                            pdc: 0x01,
+                            mut edt,
+                            ..
+                        } => {
+                            digits = Some(edt.get_u8());
+                        }
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::OPERATION_STATUS,
+                            pdc: 0x01,
+                            mut edt,
+                            ..
+                        } => {
+                            set_operation_status(&metrics.meter_operation_status, edt.get_u8());
+                        }
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::GET_PROPERTY_MAP,
+                            edt,
+                            ..
+                        } => match PropertyMap::decode(&edt) {
+                            Some(map) => {
+                                info!("smartmeter supports EPCs: {:?}", map);
+                                get_property_map = Some(map);
+                            }
+                            None => warn!("invalid Get property map {:?}", edt),
+                        },
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::INSTALLATION_LOCATION,
+                            edt,
+                            ..
+                        } => {
+                            // optional property, which some meters return with PDC 0
+                            if let Some(code) = installation_location(&edt) {
+                                info!("smartmeter installation location: {}", code);
+                                metrics.meter_location_info.reset();
+                                metrics
+                                    .meter_location_info
+                                    .with_label_values(&[code.as_str()])
+                                    .set(1.0);
+                            }
+                        }
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::FAULT_STATUS,
+                            pdc: 0x01,
+                            mut edt,
+                            ..
+                        } => {
+                            let status = edt.get_u8();
+                            match fault_status(status) {
+                                Some(true) => {
+                                    warn!("smartmeter reports a fault");
+                                    metrics.meter_fault.set(1.0);
                                 }
-                            };
+                                Some(false) => metrics.meter_fault.set(0.0),
+                                None => warn!("unknown fault status {:#x}", status),
+                            }
+                        }
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::MANUFACTURER_CODE,
+                            pdc: 0x03,
+                            edt,
+                            ..
+                        } => {
+                            if let Some((code, name)) = manufacturer(&edt) {
+                                let name = name.unwrap_or("unknown");
+                                info!("smartmeter manufacturer: {} ({})", name, code);
+                                metrics.meter_manufacturer_info.reset();
+                                metrics
+                                    .meter_manufacturer_info
+                                    .with_label_values(&[code.as_str(), name])
+                                    .set(1.0);
+                            }
+                        }
+                        // optional properties, which some meters return with PDC 0
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::PRODUCT_CODE,
+                            edt,
+                            ..
+                        } => {
+                            product = product_string(&edt);
+                        }
+                        EDataProperty {
+                            epc: EpcLowVoltageSmartMeter::PRODUCTION_NUMBER,
+                            edt,
+                            ..
+                        } => {
+                            serial = product_string(&edt);
                         }
                         _ => {
                             // ignore
                         }
                     }
                 }
+                break 'wait_response;
             }
             _ => {
             }
         }
     }
+    drop(phase);
+
+    if product.is_some() || serial.is_some() {
+        let product = product.as_deref().unwrap_or("unknown");
+        let serial = serial.as_deref().unwrap_or("unknown");
+        info!("smartmeter product: {}, serial: {}", product, serial);
+        metrics.meter_product_info.reset();
+        metrics
+            .meter_product_info
+            .with_label_values(&[product, serial])
+            .set(1.0);
+    }
 
     if unit == 0.0 {
-        return Err("Get cumulative energy unit failed".into());
+        return Err(InitError::UnitMissing);
     }
 
-    Ok((ipv6_addr, unit))
+    Ok((
+        ipv6_addr,
+        MeterInfo {
+            cumulative_energy_unit: unit,
+            coefficient,
+            digits,
+            eoj,
+            get_property_map,
+            session_lifetime,
+        },
+    ))
 }
 
-// # cancellation
-// It is caller responsibility to ensure that the previous reader thread closes before calling initialize again.
-// By dropping thre writer, reader.read() will get error and then the reader thread closes.
-// Note that reader.read() yield something no later than reader timeout set by uart.set_read_mode().
-// So, if you drop the writer, you can successfully join the reader thread within the timeout.
-fn initialize(
-) -> Result<(UartWriter, Receiver<Response>, IpAddr, JoinHandle<()>, f64), Box<dyn Error>> {
-    let mut uart =
-        TTYPort::open(&serialport::new("/dev/ttyO1", 115200)).expect("Failed to open serial port");
-    uart.set_parity(serialport::Parity::None)?;
-    uart.set_data_bits(DataBits::Eight)?;
-    uart.set_stop_bits(StopBits::One)?;
-    uart.set_timeout(Duration::from_millis(5000))?;
+// a stopped meter still answers the requests, which distinguishes it from a communication failure
+fn set_operation_status(gauge: &Gauge, status: u8) {
+    match operation_status(status) {
+        Some(true) => gauge.set(1.0),
+        Some(false) => {
+            warn!("smartmeter is not operating");
+            gauge.set(0.0);
+        }
+        None => warn!("unknown operation status {:#x}", status),
+    }
+}
 
-    let (sender, mut receiver) = channel();
-    let (mut reader, mut writer) = split_uart(uart);
+// collect the half-hourly cumulative energy history of the given day (0 = today, 1 = yesterday, ...).
+// The module sends one frame at a time, so the day is set by SetC and the history is got after Set_Res arrived.
+fn collect_historical_energy<D: SerialDevice>(
+    writer: &mut UartWriter<D>,
+    receiver: &mut Receiver<Response>,
+    ipaddr: &IpAddr,
+    eoj: Eoj,
+    sec: u8,
+    epc: u8,
+    day: u8,
+) -> Result<HistoricalCumulativeEnergy, Box<dyn Error>> {
+    let seoj = controller_eoj();
+    let tid = next_tid();
+    writer.send_command(Command::SendSetDayForHistoricalData {
+        ipaddr,
+        sec,
+        seoj,
+        tid,
+        day,
+    })?;
+    let reply = recv_meter_reply(receiver, eoj, tid, ENERGY_RESPONSE_TIMEOUT, "SetC of EPC 0xE5")?;
+    if Esv::is_sna(reply.esv) {
+        return Err(format!("smartmeter rejected day {} of historical data", day).into());
+    }
+
+    let tid = next_tid();
+    writer.send_command(Command::SendGetRequest {
+        ipaddr,
+        sec,
+        seoj,
+        tid,
+        epcs: &[epc],
+    })?;
+    let reply = recv_meter_reply(receiver, eoj, tid, ENERGY_RESPONSE_TIMEOUT, "Get of historical energy")?;
+    reply
+        .props
+        .iter()
+        .find(|prop| prop.epc == epc)
+        .and_then(|prop| parse_historical_cumulative_energy(&prop.edt))
+        .ok_or_else(|| "Invalid historical cumulative energy".into())
+}
+
+// wait for the reply of `eoj` to the request of `tid`, skipping the frames of the other requests
+fn recv_meter_reply(
+    receiver: &mut Receiver<Response>,
+    eoj: Eoj,
+    tid: u16,
+    timeout: Duration,
+    command: &str,
+) -> Result<EDataFormat1, Box<dyn Error>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let r = recv_response_timeout(receiver, remaining, command).map_err(|e| match e {
+            InitError::CommandTimeout { command, .. } => InitError::CommandTimeout { command, timeout },
+            e => e,
+        })?;
+        match r {
+            Response::SkSendTo { result: Some(result), .. } if result != UDP_SEND_SUCCESS => {
+                return Err(format!("{} could not be sent", command).into());
+            }
+            Response::Event { num: 0x21, param: Some(UDP_SEND_FAILURE), .. } => {
+                return Err(format!("{} could not be sent", command).into());
+            }
+            Response::ERxUdp { secured: 0x00, ref sender, .. } => {
+                warn!("ignore unsecured frame from {}", sender);
+            }
+            Response::ERxUdp {
+                data:
+                    EchonetLite {
+                        ehd,
+                        edata: EData::EDataFormat1(format1),
+                    },
+                ..
+            } if ehd.tid == tid && format1.seoj == eoj => return Ok(format1),
+            _ => {}
+        }
+    }
+}
+
+// history is not exported as gauges, but logged as records so that a separate tool can ingest it
+fn log_historical_energy(history: &HistoricalCumulativeEnergy, epc: u8, meter_info: &MeterInfo) {
+    let (message, field) = match epc {
+        EpcLowVoltageSmartMeter::HISTORICAL_CUMULATIVE_ENERGY_REVERSE_DIRECTION => {
+            ("historical cumulative energy reverse", "cumulative_energy_reverse")
+        }
+        _ => ("historical cumulative energy", "cumulative_energy"),
+    };
+    for (slot, value) in history.values.iter().enumerate() {
+        if let Some(raw) = value {
+            info!(
+                "{}: day={} time={:02}:{:02} {}={}",
+                message,
+                history.day,
+                slot / 2,
+                (slot % 2) * 30,
+                field,
+                echonet_lite::cumulative_energy(
+                    *raw,
+                    meter_info.coefficient,
+                    meter_info.cumulative_energy_unit
+                )
+            );
+        }
+    }
+}
+
+// 0 disables collecting the historical energy
+fn history_days() -> u8 {
+    env_parse_valid("HISTORY_DAYS", 0, |days| *days <= MAX_HISTORY_DAYS)
+}
+
+// the label of counter_parse_error
+fn parse_error_kind<E>(e: &nom::Err<E>) -> &'static str {
+    match e {
+        // only counted when the input ends, since more bytes usually complete the response
+        nom::Err::Incomplete(_) => "incomplete",
+        // no parser matched, e.g. a line broken by noise
+        nom::Err::Error(_) => "error",
+        // a parser matched but the content is invalid
+        nom::Err::Failure(_) => "failure",
+    }
+}
+
+// resets reader_thread_alive when the reader thread exits, including by panic
+struct ReaderAliveGuard(Gauge);
+
+impl Drop for ReaderAliveGuard {
+    fn drop(&mut self) {
+        self.0.set(0.0);
+    }
+}
+
+// parse the responses from the module in a thread, which closes when the writer is dropped.
+// At most `bound` responses wait for the receiver, further ones are dropped unless `block_when_full`,
+// which is for the replay where nothing should be lost and the file can wait.
+fn spawn_reader<D: SerialDevice>(
+    mut reader: UartReader<D>,
+    mut capture: Option<Capture>,
+    metrics: ReaderMetrics,
+    bound: usize,
+    block_when_full: bool,
+) -> (Receiver<Response>, JoinHandle<()>) {
+    let (sender, receiver) = sync_channel(bound);
 
     let handle = std::thread::spawn(move || {
-        let mut buf = BytesMut::with_capacity(1024);
-        loop {
+        metrics.reader_thread_alive.set(1.0);
+        let _alive = ReaderAliveGuard(metrics.reader_thread_alive.clone());
+        // false if the receiver is dropped
+        let send = |response: Response| -> bool {
+            if block_when_full {
+                return sender.send(response).is_ok();
+            }
+            match sender.try_send(response) {
+                Ok(()) => true,
+                // the timeout is only a tick for the receiver, the next one will do
+                Err(TrySendError::Full(Response::UartTimeOut)) => true,
+                Err(TrySendError::Full(response)) => {
+                    warn!("receiver is not keeping up, drop response: {}", response);
+                    metrics.counter_reader_dropped.inc();
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        };
+        let mut buf = ResponseBuffer::new();
+        // # of consecutive parse errors
+        let mut parse_errors = 0;
+        'read: loop {
             let mut b = [0; 1024];
+            let mut eof = false;
 
             match reader.read(&mut b) {
                 Ok(n) if n > 0 => {
                     debug!("read: {:?}", &b[..n]);
-                    buf.put(&b[..n]);
+                    metrics.uart_bytes_read_total.inc_by(n as f64);
+                    buf.extend(&b[..n]);
+                    if let Some(c) = &mut capture {
+                        if let Err(e) = c.write_chunk(unix_time(), &b[..n]) {
+                            warn!("failed to write capture, stop capturing: {:?}", e);
+                            capture = None;
+                        }
+                    }
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::TimedOut => {
-                    sender.send(Response::UartTimeOut).unwrap();
+                    if let Some(c) = &mut capture {
+                        if let Err(e) = c.flush() {
+                            warn!("failed to flush capture, stop capturing: {:?}", e);
+                            capture = None;
+                        }
+                    }
+                    if !send(Response::UartTimeOut) {
+                        warn!("receiver is dropped, stop reading");
+                        break;
+                    }
                     continue;
                 }
+                Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    // the replay file is exhausted, parse what is left and finish
+                    info!("end of input: {:?}", e);
+                    eof = true;
+                }
                 Err(e) => {
                     error!("uart read error: {:?}", e);
                     break;
@@ -339,189 +1548,1884 @@ fn initialize(
                 _ => {}
             }
 
-            debug!("current buf: {:?}", buf);
-            match parser(&buf) {
-                Ok((rest, line)) => {
-                    debug!("parsed response: {:?}", line);
-                    sender.send(line).unwrap();
-
-                    buf = BytesMut::from(rest);
-                }
-                Err(nom::Err::Incomplete(n)) => {
+            debug!("current buf: {:?}", buf.as_bytes());
+            // a single read may contain several responses
+            loop {
+                match buf.parse_next() {
+                    Ok(Some(line)) => {
+                        debug!("parsed response: {}", line);
+                        metrics.uart_frames_parsed_total.inc();
+                        if !send(line) {
+                            warn!("receiver is dropped, stop reading");
+                            break 'read;
+                        }
+                        parse_errors = 0;
+                    }
                     // not enough data
-                    debug!("parse incomplate: {:?}", n);
+                    Ok(None) => break,
+                    Err(e) => {
+                        metrics.counter_parse_error.with_label_values(&[parse_error_kind(&e)]).inc();
+                        parse_errors += 1;
+                        if parse_errors > MAX_CONSECUTIVE_PARSE_ERRORS {
+                            error!("parse error: {:?}", e);
+
+                            // finish reading from device
+                            break 'read;
+                        }
+                        // a line broken by noise on the UART should not kill the session, so skip to the next line
+                        warn!("parse error, resynchronize at the next line: {:?}", e);
+                        metrics.counter_parser_resync.inc();
+                        buf.resync();
+                    }
                 }
-                Err(e) => {
-                    error!("parse error: {:?}", e);
+            }
 
-                    // finish reading from device
-                    break;
+            if eof {
+                if !buf.is_empty() {
+                    warn!("input ended in the middle of a response: {:?}", buf.as_bytes());
+                    metrics.counter_parse_error.with_label_values(&["incomplete"]).inc();
                 }
+                break;
             }
         }
         // explicitly drop sender, so that receiver.recv() will return Err
         drop(sender);
     });
 
-    let (ipv6_addr, unit) = match send_initialize_command_sequence(&mut writer, &mut receiver) {
+    (receiver, handle)
+}
+
+// # cancellation
+// It is caller responsibility to ensure that the previous reader thread closes before calling initialize again.
+// By dropping thre writer, reader.read() will get error and then the reader thread closes.
+// Note that reader.read() yield something no later than reader timeout set by uart.set_read_mode().
+// So, if you drop the writer, you can successfully join the reader thread within the timeout.
+fn initialize(
+    b_id: &str,
+    b_pw: &str,
+    args: &Args,
+    metrics: &Metrics,
+) -> Result<(UartWriter<Uart>, Receiver<Response>, IpAddr, JoinHandle<()>, MeterInfo), InitError> {
+    let uart = match replay_file() {
+        Some(path) => {
+            info!("replaying UART from {}, commands are discarded", path);
+            Uart::Replay(ReplayFile::open(&path).map_err(|e| InitError::Open(e.into()))?)
+        }
+        None => {
+            let mut tty = open_serial_port(args).map_err(InitError::Open)?;
+            // partial frames left by the previous session would make the first responses unparsable
+            drain_stale_input(&mut tty).map_err(|e| InitError::Open(e.into()))?;
+            Uart::Tty(tty)
+        }
+    };
+
+    let capture = match std::env::var("CAPTURE_FILE") {
+        Ok(path) => match Capture::open(&path) {
+            Ok(capture) => {
+                info!("capturing UART to {}", path);
+                Some(capture)
+            }
+            Err(e) => {
+                warn!("failed to open capture file {:?}: {:?}", path, e);
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    let replay = matches!(uart, Uart::Replay(_));
+    let (reader, mut writer) = split_uart(uart);
+    let (mut receiver, handle) =
+        spawn_reader(reader, capture, metrics.reader(), reader_channel_bound(), replay);
+
+    let (ipv6_addr, meter_info) = match send_initialize_command_sequence(&mut writer, &mut receiver, b_id, b_pw, metrics) {
         Ok(ipv6_addr) => ipv6_addr,
         Err(e) => {
             drop(writer);
+            // a reader blocked on the full channel returns once the receiver is dropped
+            drop(receiver);
             handle.join().expect("failed to join the reader thread");
+            ConnectionState::Disconnected.set(&metrics.connection_state);
             return Err(e);
         }
     };
 
-    Ok((writer, receiver, ipv6_addr, handle, unit))
+    Ok((writer, receiver, ipv6_addr, handle, meter_info))
+}
+
+fn open_serial_port(args: &Args) -> Result<TTYPort, Box<dyn Error>> {
+    let port = serial_port(args.serial_port.as_deref())?;
+    let baud = parse_required("SERIAL_BAUD", args.serial_baud.as_deref())?.unwrap_or(DEFAULT_SERIAL_BAUD);
+
+    // an unplugged dongle is retried with backoff like the other initialization errors
+    let mut uart = match TTYPort::open(&serialport::new(&port, baud)) {
+        Ok(uart) => uart,
+        Err(e) => {
+            error!("failed to open serial port {:?}: {}", port, e);
+            return Err(e.into());
+        }
+    };
+    uart.set_parity(serialport::Parity::None)?;
+    uart.set_data_bits(DataBits::Eight)?;
+    uart.set_stop_bits(StopBits::One)?;
+    uart.set_timeout(Duration::from_millis(5000))?;
+
+    // some USB dongles drop the commands written right after the port is opened
+    let delay = startup_delay();
+    if !delay.is_zero() {
+        debug!("waiting {:?} for the device to settle", delay);
+        std::thread::sleep(delay);
+    }
+    // discard the boot banner and anything else the module printed before
+    uart.clear(ClearBuffer::Input)?;
+
+    Ok(uart)
+}
+
+// read and discard whatever arrives within STALE_INPUT_READ_TIMEOUT, for STALE_INPUT_MAX_DURATION at most
+fn drain_stale_input<P: SerialPort>(port: &mut P) -> io::Result<()> {
+    let timeout = port.timeout();
+    port.set_timeout(STALE_INPUT_READ_TIMEOUT)?;
+    let deadline = Instant::now() + STALE_INPUT_MAX_DURATION;
+    let mut discarded = 0;
+    let mut buf = [0u8; 1024];
+    let result = loop {
+        if Instant::now() >= deadline {
+            warn!("serial port keeps sending, stop discarding stale input");
+            break Ok(());
+        }
+        match port.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(n) => discarded += n,
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+    if discarded > 0 {
+        info!("discarded {} bytes of stale input", discarded);
+    }
+    port.set_timeout(timeout)?;
+    result
+}
+
+fn startup_delay() -> Duration {
+    Duration::from_millis(env_parse("STARTUP_DELAY_MS", DEFAULT_STARTUP_DELAY.as_millis() as u64))
+}
+
+// SERIAL_BY_ID or SERIAL_USB_ID takes precedence over SERIAL_PORT, since the node they resolve to may change
+fn serial_port(serial_port: Option<&str>) -> Result<String, Box<dyn Error>> {
+    let index = parse_required("SERIAL_INDEX", std::env::var("SERIAL_INDEX").ok().as_deref())?;
+    let port = if let Ok(pattern) = std::env::var("SERIAL_BY_ID") {
+        serial_device::resolve_by_id(&pattern, index)?
+    } else if let Ok(usb_id) = std::env::var("SERIAL_USB_ID") {
+        serial_device::resolve_usb_id(&usb_id, index)?
+    } else {
+        return Ok(serial_port.unwrap_or(DEFAULT_SERIAL_PORT).to_string());
+    };
+    info!("using serial port {}", port);
+    Ok(port)
+}
+
+// development mode which feeds a recorded capture to the parser instead of the serial port
+fn replay_file() -> Option<String> {
+    std::env::var("REPLAY_FILE").ok()
+}
+
+// properties requested once after connected
+const INITIAL_REQUEST_EPCS: &[u8] = &[
+    EpcLowVoltageSmartMeter::OPERATION_STATUS,
+    EpcLowVoltageSmartMeter::INSTALLATION_LOCATION,
+    EpcLowVoltageSmartMeter::FAULT_STATUS,
+    EpcLowVoltageSmartMeter::MANUFACTURER_CODE,
+    EpcLowVoltageSmartMeter::PRODUCT_CODE,
+    EpcLowVoltageSmartMeter::PRODUCTION_NUMBER,
+    EpcLowVoltageSmartMeter::GET_PROPERTY_MAP,
+    EpcLowVoltageSmartMeter::COEFFICIENT,
+    EpcLowVoltageSmartMeter::EFFECTIVE_DIGITS_OF_CUMULATIVE_ENERGY,
+    EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_UNIT,
+];
+
+// properties requested on every poll, all of them are sent in a single frame
+const ENERGY_REQUEST_EPCS: &[u8] = &[
+    EpcLowVoltageSmartMeter::OPERATION_STATUS,
+    EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+    EpcLowVoltageSmartMeter::INSTANTANEOUS_CURRENT,
+    EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION,
+    EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_REVERSE_DIRECTION,
+];
+
+const CUMULATIVE_ENERGY_EPCS: &[u8] = &[
+    EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION,
+    EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_REVERSE_DIRECTION,
+];
+
+// the meter keeps the history of the last 99 days at most
+const MAX_HISTORY_DAYS: u8 = 99;
+
+// # of TIDs of handled responses remembered to detect duplicated frames
+const SEEN_TIDS_CAPACITY: usize = 16;
+const INITIALIZE_BACKOFF_BASE: Duration = Duration::from_secs(5);
+const INITIALIZE_BACKOFF_MAX: Duration = Duration::from_secs(300);
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(10);
+// S16 of BP35A1
+const SREG_PANA_SESSION_LIFETIME: u8 = 0x16;
+const DEFAULT_PANA_SESSION_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+const DEFAULT_PANA_RENEW_BEFORE: Duration = Duration::from_secs(60);
+const DEFAULT_CMD_TIMEOUT_MS: u64 = 5000;
+const ENERGY_RESPONSE_TIMEOUT: Duration = Duration::from_secs(19);
+const UNIT_RESPONSE_TIMEOUT: Duration = Duration::from_secs(19);
+const DEFAULT_UNIT_REQUEST_MAX_RESENDS: u32 = 2;
+const SKSENDTO_MAX_RESENDS: u32 = 2;
+// param of EVENT 0x21
+const UDP_SEND_SUCCESS: u8 = 0x00;
+const UDP_SEND_FAILURE: u8 = 0x01;
+const SKSENDTO_RESEND_DELAY: Duration = Duration::from_secs(1);
+// a transient failure is resent within the poll cycle, this many of them suggest a congested channel
+const DEFAULT_CHANNEL_RESCAN_THRESHOLD: usize = 10;
+const DEFAULT_CHANNEL_RESCAN_WINDOW: Duration = Duration::from_secs(600);
+const DEFAULT_MAX_EMPTY_CYCLES: u32 = 3;
+const STALE_INPUT_READ_TIMEOUT: Duration = Duration::from_millis(100);
+const STALE_INPUT_MAX_DURATION: Duration = Duration::from_secs(1);
+const INFO_TIMEOUT: Duration = Duration::from_secs(5);
+
+// the reader thread gives up after this many lines in a row can not be parsed
+const MAX_CONSECUTIVE_PARSE_ERRORS: u32 = 5;
+// a poll cycle yields a handful of responses, so this only fills up when the main loop is stuck
+const DEFAULT_READER_CHANNEL_BOUND: usize = 64;
+
+const SCAN_MIN_DURATION: u8 = 6;
+// SKSCAN accepts duration up to 14
+const SCAN_MAX_DURATION: u8 = 14;
+const DEFAULT_SCAN_MAX_ATTEMPTS: u8 = 3;
+const PANDESC_CACHE_MAX_FAILURES: u8 = 3;
+
+const DEFAULT_SERIAL_PORT: &str = "/dev/ttyO1";
+const DEFAULT_SERIAL_BAUD: u32 = 115200;
+const DEFAULT_STARTUP_DELAY: Duration = Duration::ZERO;
+const DEFAULT_LISTEN_ADDR: &str = "0.0.0.0:9186";
+const DEFAULT_HEALTH_LISTEN_ADDR: &str = "0.0.0.0:9187";
+const DEFAULT_HEALTH_PATH: &str = "/healthz";
+const DEFAULT_LOG_FILE: &str = "/var/log/smartmeter-exporter/smartmeter-exporter.log";
+const DEFAULT_POLL_INTERVAL_MS: u64 = 10000;
+const DEFAULT_CUMULATIVE_INTERVAL_SECS: u64 = 1800;
+// from standby power to the usual 30-60A contract of a household
+const DEFAULT_ENERGY_HISTOGRAM_BUCKETS: &[f64] = &[
+    0.0, 100.0, 250.0, 500.0, 750.0, 1000.0, 1500.0, 2000.0, 3000.0, 4000.0, 5000.0,
+];
+const MIN_POLL_INTERVAL_MS: u64 = 1000;
+
+// B-route ID is always 32 alphanumeric characters
+const B_ID_LEN: usize = 32;
+
+// the credentials file is preferred to the environment variables, which are visible in /proc/<pid>/environ
+fn read_credentials(credentials_file: Option<&str>) -> Result<(String, Zeroizing<String>), Box<dyn Error>> {
+    let mut file = match credentials_file {
+        Some(path) => credentials::load(path)
+            .map_err(|e| format!("can not read credentials file {:?}: {}", path, e))?,
+        None => Default::default(),
+    };
+    let b_id = match file.remove("B_ID") {
+        Some(b_id) => b_id.to_string(),
+        None => std::env::var("B_ID").map_err(|_| "B_ID is not set")?,
+    };
+    let b_pw = match file.remove("B_PW") {
+        Some(b_pw) => b_pw,
+        None => Zeroizing::new(std::env::var("B_PW").map_err(|_| "B_PW is not set")?),
+    };
+
+    if b_id.len() != B_ID_LEN || !b_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!(
+            "B_ID must be {} alphanumeric characters, but got {} characters",
+            B_ID_LEN,
+            b_id.len()
+        )
+        .into());
+    }
+
+    Ok((b_id, b_pw))
 }
 
-const B_ID: &str = std::env!("B_ID");
-const B_PW: &str = std::env!("B_PW");
+// # of consecutive failures to join with the cached PAN descriptor
+static PANDESC_CACHE_FAILURES: AtomicU8 = AtomicU8::new(0);
+
+// set to scan on the next initialization instead of joining the configured or cached PAN
+static FORCE_SCAN: AtomicBool = AtomicBool::new(false);
+
+// the TID of ECHONET Lite request, incremented for each request
+static NEXT_TID: AtomicU16 = AtomicU16::new(1);
+
+fn next_tid() -> u16 {
+    NEXT_TID.fetch_add(1, Ordering::Relaxed)
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+fn poll_interval(raw: Option<&str>) -> Duration {
+    let interval_ms = parse_or("POLL_INTERVAL_MS", raw, DEFAULT_POLL_INTERVAL_MS, |_| true);
+
+    // Wi-SUN module can not reliably respond faster than once per second
+    if interval_ms < MIN_POLL_INTERVAL_MS {
+        warn!(
+            "POLL_INTERVAL_MS {}ms is too short, use {}ms instead",
+            interval_ms, MIN_POLL_INTERVAL_MS
+        );
+        return Duration::from_millis(MIN_POLL_INTERVAL_MS);
+    }
+
+    Duration::from_millis(interval_ms)
+}
+
+// a reading printed to stdout as a line of JSON when OUTPUT_JSON=1, and published to MQTT / InfluxDB
+#[derive(Debug, Serialize)]
+struct Reading {
+    timestamp: f64,
+    instantaneous_watt: f64,
+    cumulative_kwh: f64,
+    cumulative_reverse_kwh: f64,
+}
+
+fn output_json() -> bool {
+    matches!(std::env::var("OUTPUT_JSON").as_deref(), Ok("1"))
+}
+
+// The metrics are still kept in memory since the readings for the other outputs are taken from the gauges,
+// only they are not served over HTTP.
+fn disable_prometheus() -> bool {
+    matches!(std::env::var("DISABLE_PROMETHEUS").as_deref(), Ok("1"))
+}
+
+// a no-op unless started by systemd with NOTIFY_SOCKET
+fn notify_systemd(state: NotifyState) {
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        warn!("failed to notify systemd: {:?}", e);
+    }
+}
+
+// the time source of the poll cycle, replaced in tests not to wait for the resend delay
+trait Clock {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+// how a poll cycle ended
+#[derive(Debug, PartialEq, Eq)]
+enum PollResult {
+    // the response to the energy request is handled, with or without instantaneous energy
+    Response { instantaneous_energy: bool },
+    Timeout,
+    // the session is broken, or the reader thread is gone
+    Reconnect,
+    // EVENT 0x21 send failures piled up, so the channel is likely congested
+    Rescan,
+}
+
+// the handles and the state of the poll cycles, kept across reconnects
+struct Poller<'a, C: Clock> {
+    metrics: &'a Metrics,
+    clock: C,
+    sec: u8,
+    output_json: bool,
+    mqtt: Option<MqttPublisher>,
+    influx: Option<InfluxWriter>,
+    // the last cumulative energy of each direction, from which the counters are increased.
+    // The first reading after start only sets the gauges, and the counters start from 0.
+    last_cumulative_energy: Option<f64>,
+    last_cumulative_energy_reverse: Option<f64>,
+    smoothed_energy: Option<ExponentialMovingAverage>,
+    energy_histogram: Option<Histogram>,
+    seen_tids: SeenTids,
+    send_failures: SendFailures,
+}
+
+impl<C: Clock> Poller<'_, C> {
+    // sends the energy request of `epcs`, and updates the gauges with the response
+    fn poll<D: SerialDevice>(
+        &mut self,
+        writer: &mut UartWriter<D>,
+        receiver: &mut Receiver<Response>,
+        ipv6_addr: &IpAddr,
+        meter_info: &MeterInfo,
+        tid: u16,
+        epcs: &[u8],
+    ) -> PollResult {
+        if let Err(e) = writer.send_command(Command::SendGetRequest {
+            ipaddr: ipv6_addr,
+            sec: self.sec,
+            seoj: controller_eoj(),
+            tid,
+            epcs,
+        }) {
+            error!("failed to send command: {:?}", e);
+            self.metrics.counter_error_sksendto.inc();
+            return PollResult::Reconnect;
+        }
+        self.metrics.counter_request_energy.inc();
+        let mut deadline = self.clock.now() + ENERGY_RESPONSE_TIMEOUT;
+        let mut resends = 0;
+        let mut got_instantaneous_energy = false;
+
+        // wait response for energy request
+        loop {
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            let r = match receiver.recv_timeout(remaining) {
+                Ok(r) => r,
+                Err(RecvTimeoutError::Timeout) => {
+                    warn!("no response for energy request within {:?}", ENERGY_RESPONSE_TIMEOUT);
+                    self.metrics.counter_response_timeout.inc();
+                    return PollResult::Timeout;
+                }
+                Err(e) => {
+                    error!("reader thread closed when they encouter error: {:?}", e);
+                    return PollResult::Reconnect;
+                }
+            };
+            info!("got response {}", r);
+
+            match r {
+                Response::SkSendTo { result: Some(UDP_SEND_SUCCESS) | None, .. } => {
+                    debug!("send energy request success");
+                }
+                Response::Event { num: 0x21, param, .. } if param != Some(UDP_SEND_FAILURE) => {
+                    // 0x02 means the neighbor solicitation is sent, and the request follows
+                    debug!("UDP send event of energy request: {:?}", param);
+                }
+                // the failure of UDP transmission may be reported separately after SKSENDTO is accepted,
+                // and FAIL ERnn (e.g. ER10 while the module is busy) is resent likewise
+                Response::SkSendTo { .. } | Response::Event { num: 0x21, .. } | Response::Fail { .. } => {
+                    warn!("failed to send energy request: {}", r);
+                    self.metrics.counter_error_sksendto.inc();
+                    if matches!(r, Response::Event { num: 0x21, .. })
+                        && self.send_failures.record(self.clock.now())
+                    {
+                        warn!("send failures piled up on the channel, scanning again");
+                        return PollResult::Rescan;
+                    }
+                    // the failure is often transient, so resend before reconnecting
+                    if resends >= SKSENDTO_MAX_RESENDS {
+                        error!("energy request failed {} times, reconnecting", resends + 1);
+                        return PollResult::Reconnect;
+                    }
+                    self.clock.sleep(SKSENDTO_RESEND_DELAY);
+                    resends += 1;
+                    self.metrics.counter_sksendto_retry.inc();
+                    if let Err(e) = writer.send_command(Command::SendGetRequest {
+                        ipaddr: ipv6_addr,
+                        sec: self.sec,
+                        seoj: controller_eoj(),
+                        tid,
+                        epcs,
+                    }) {
+                        error!("failed to send command: {:?}", e);
+                        return PollResult::Reconnect;
+                    }
+                    deadline = self.clock.now() + ENERGY_RESPONSE_TIMEOUT;
+                }
+                Response::Event { num: 0x29, .. } => {
+                    // all SKSENDTO fail after the session expired, so reconnect immediately
+                    warn!("PANA session expired, reconnecting");
+                    return PollResult::Reconnect;
+                }
+                Response::ERxUdp { secured: 0x00, ref sender, .. } => {
+                    // unencrypted frames are likely spoofed, trust only authenticated frames
+                    warn!("ignore unsecured frame from {}", sender);
+                }
+                Response::ERxUdp {
+                    ref sender,
+                    data:
+                        EchonetLite {
+                            edata: EData::EDataFormat2(ref data),
+                            ..
+                        },
+                    ..
+                } => {
+                    info!("ignore ECHONET Lite format 2 frame from {}: {:?}", sender, data);
+                }
+                Response::ERxUdp {
+                    ref sender,
+                    data:
+                        EchonetLite {
+                            edata: EData::EDataFormat1(EDataFormat1 { seoj, .. }),
+                            ..
+                        },
+                    ..
+                } if seoj != meter_info.eoj => {
+                    // another meter or another instance on the same PAN
+                    warn!("ignore frame from foreign object {} at {}", seoj, sender);
+                    self.metrics.counter_foreign_frame.inc();
+                }
+                Response::ERxUdp {
+                    data:
+                        EchonetLite {
+                            ref ehd,
+                            edata: EData::EDataFormat1(_),
+                        },
+                    ..
+                } if ehd.tid != tid => {
+                    if self.seen_tids.contains(ehd.tid) {
+                        // the module may deliver the same frame twice over a lossy link
+                        debug!("drop duplicate frame with TID {:#x}", ehd.tid);
+                        self.metrics.counter_duplicate_frame.inc();
+                    } else {
+                        // a late response to an earlier request
+                        debug!("drop frame with mismatched TID {:#x} (expected {:#x})", ehd.tid, tid);
+                    }
+                }
+                Response::ERxUdp {
+                    data:
+                        EchonetLite {
+                            edata:
+                                EData::EDataFormat1(EDataFormat1 {
+                                    esv,
+                                    props,
+                                    ..
+                                }),
+                            ..
+                        },
+                    ..
+                } => {
+                    self.seen_tids.insert(tid);
+                    if Esv::is_sna(esv) {
+                        // properties which could not be read are returned without data,
+                        // the others are handled as usual
+                        for prop in props.iter().filter(|prop| prop.pdc == 0x00) {
+                            warn!(
+                                "smartmeter could not serve EPC {:#x} (ESV {:#x})",
+                                prop.epc, esv
+                            );
+                            self.metrics.counter_echonet_sna.inc();
+                        }
+                    }
+                    // the per-phase power needs both the total power and the currents of the response
+                    let mut power_total = None;
+                    let mut currents = None;
+                    // properties without data are counted above, and PDC of the others is checked by the parser
+                    for prop in props.into_iter().filter(|prop| prop.pdc != 0x00) {
+                        match prop {
+                            EDataProperty {
+                                epc: EpcLowVoltageSmartMeter::OPERATION_STATUS,
+                                edt,
+                                ..
+                            } => match edt.first() {
+                                Some(&status) => set_operation_status(&self.metrics.meter_operation_status, status),
+                                None => warn!("ignore truncated operation status: {:?}", edt),
+                            },
+                            EDataProperty {
+                                epc: EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+                                edt,
+                                ..
+                            } => {
+                                let power = match instantaneous_energy(&edt) {
+                                    Some(power) => power,
+                                    None => {
+                                        warn!("ignore truncated instantaneous energy: {:?}", edt);
+                                        continue;
+                                    }
+                                };
+                                self.metrics.instantaneous_energy.set(power as f64);
+                                if let Some(ema) = &mut self.smoothed_energy {
+                                    self.metrics.instantaneous_energy_smoothed.set(ema.update(power as f64));
+                                }
+                                if let Some(histogram) = &self.energy_histogram {
+                                    histogram.observe(power as f64);
+                                }
+                                self.metrics.last_reading_unixtime.set(unix_time());
+                                power_total = Some(power);
+                                got_instantaneous_energy = true;
+                            }
+                            EDataProperty {
+                                epc: EpcLowVoltageSmartMeter::INSTANTANEOUS_CURRENT,
+                                edt,
+                                ..
+                            } => {
+                                if let Some((current_r, current_t)) = instantaneous_current(&edt) {
+                                    self.metrics.instantaneous_current_r.set(current_r);
+                                    if let Some(current_t) = current_t {
+                                        self.metrics.instantaneous_current_t.set(current_t);
+                                    }
+                                    currents = Some((current_r, current_t));
+                                }
+                            }
+                            EDataProperty {
+                                epc: EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION,
+                                edt,
+                                ..
+                            } => {
+                                let power = match fixed_time_cumulative_energy(&edt) {
+                                    Some(power) => power,
+                                    None => {
+                                        warn!("ignore truncated cumulative energy: {:?}", edt);
+                                        continue;
+                                    }
+                                };
+                                // the meter takes the snapshot every 30 minutes
+                                if let Some(time) = fixed_time_unix(&edt) {
+                                    if time as f64 == self.metrics.cumulative_energy_reading_time.get() {
+                                        debug!("fixed-time cumulative energy has not advanced since {}", time);
+                                    }
+                                    self.metrics.cumulative_energy_reading_time.set(time as f64);
+                                }
+                                self.metrics.cumulative_energy_raw.set(power as f64);
+                                let energy = echonet_lite::cumulative_energy(
+                                    power,
+                                    meter_info.coefficient,
+                                    meter_info.cumulative_energy_unit,
+                                );
+                                if meter_info.digits.is_some_and(|digits| is_cumulative_energy_rollover(
+                                    self.metrics.cumulative_energy.get(),
+                                    energy,
+                                    digits,
+                                    meter_info.coefficient,
+                                    meter_info.cumulative_energy_unit,
+                                )) {
+                                    info!("cumulative energy rolled over: {} -> {}", self.metrics.cumulative_energy.get(), energy);
+                                }
+                                if let Some(last) = self.last_cumulative_energy {
+                                    self.metrics.cumulative_energy_total.inc_by(cumulative_energy_increase(
+                                        last,
+                                        energy,
+                                        meter_info.digits,
+                                        meter_info.coefficient,
+                                        meter_info.cumulative_energy_unit,
+                                    ));
+                                }
+                                self.last_cumulative_energy = Some(energy);
+                                self.metrics.cumulative_energy.set(energy);
+                                self.metrics.last_reading_unixtime.set(unix_time());
+                            }
+                            EDataProperty {
+                                epc: EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_REVERSE_DIRECTION,
+                                edt,
+                                ..
+                            } => {
+                                let power = match fixed_time_cumulative_energy(&edt) {
+                                    Some(power) => power,
+                                    None => {
+                                        warn!("ignore truncated cumulative energy in reverse direction: {:?}", edt);
+                                        continue;
+                                    }
+                                };
+                                self.metrics.cumulative_energy_reverse_raw.set(power as f64);
+                                let energy = echonet_lite::cumulative_energy(
+                                    power,
+                                    meter_info.coefficient,
+                                    meter_info.cumulative_energy_unit,
+                                );
+                                if meter_info.digits.is_some_and(|digits| is_cumulative_energy_rollover(
+                                    self.metrics.cumulative_energy_reverse.get(),
+                                    energy,
+                                    digits,
+                                    meter_info.coefficient,
+                                    meter_info.cumulative_energy_unit,
+                                )) {
+                                    info!("cumulative energy in reverse direction rolled over: {} -> {}", self.metrics.cumulative_energy_reverse.get(), energy);
+                                }
+                                if let Some(last) = self.last_cumulative_energy_reverse {
+                                    self.metrics.cumulative_energy_reverse_total.inc_by(cumulative_energy_increase(
+                                        last,
+                                        energy,
+                                        meter_info.digits,
+                                        meter_info.coefficient,
+                                        meter_info.cumulative_energy_unit,
+                                    ));
+                                }
+                                self.last_cumulative_energy_reverse = Some(energy);
+                                self.metrics.cumulative_energy_reverse.set(energy);
+                                self.metrics.last_reading_unixtime.set(unix_time());
+                            }
+                            _ => {
+                                // ignore
+                            }
+                        }
+                    }
+                    if let (Some(power), Some((current_r, current_t))) = (power_total, currents) {
+                        match instantaneous_power_per_phase(power, current_r, current_t) {
+                            Some((power_r, power_t)) => {
+                                self.metrics.instantaneous_energy_phase.with_label_values(&["r"]).set(power_r);
+                                self.metrics.instantaneous_energy_phase.with_label_values(&["t"]).set(power_t);
+                            }
+                            // only the total in instantaneous_energy, e.g. for single-phase 2-wire meters
+                            None => self.metrics.instantaneous_energy_phase.reset(),
+                        }
+                    }
+                    let reading = Reading {
+                        timestamp: self.metrics.last_reading_unixtime.get(),
+                        instantaneous_watt: self.metrics.instantaneous_energy.get(),
+                        cumulative_kwh: self.metrics.cumulative_energy.get(),
+                        cumulative_reverse_kwh: self.metrics.cumulative_energy_reverse.get(),
+                    };
+                    if self.output_json {
+                        match serde_json::to_string(&reading) {
+                            Ok(line) => println!("{}", line),
+                            Err(e) => warn!("failed to serialize reading {:?}: {:?}", reading, e),
+                        }
+                    }
+                    if let Some(mqtt) = &self.mqtt {
+                        mqtt.publish_reading(&reading);
+                    }
+                    if let Some(influx) = &self.influx {
+                        influx.write(&reading);
+                    }
+                    notify_systemd(NotifyState::Watchdog);
+                    return PollResult::Response {
+                        instantaneous_energy: got_instantaneous_energy,
+                    };
+                }
+                _ => {
+                    // ignore
+                }
+            }
+        }
+    }
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+
     let env = Env::default().default_filter_or("debug");
     let mut builder = Builder::from_env(env);
 
     if let Ok(dest) = std::env::var("RUST_LOG_DESTINATION") {
         if dest == "file" {
-            let file = OpenOptions::new()
-                .append(true)
-                .create(true)
-                .open("/var/log/smartmeter-exporter/smartmeter-exporter.log")?;
+            let path = std::env::var("LOG_FILE").unwrap_or_else(|_| DEFAULT_LOG_FILE.to_string());
+            let reopen = Arc::new(AtomicBool::new(false));
+            flag::register(SIGHUP, Arc::clone(&reopen))?;
+            let file = LogFile::open(&path, reopen)?;
             builder.target(Target::Pipe(Box::new(file)));
         }
     }
     builder.init();
 
-    let addr_raw = "0.0.0.0:9186";
-    let addr: SocketAddr = addr_raw.parse().expect("can not parse listen addr");
+    // The first SIGINT/SIGTERM requests a graceful shutdown, which terminates the PANA session.
+    // A second signal while shutting down exits immediately.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for sig in TERM_SIGNALS {
+        flag::register_conditional_shutdown(*sig, 1, Arc::clone(&shutdown))?;
+        flag::register(*sig, Arc::clone(&shutdown))?;
+    }
 
-    let exporter = prometheus_exporter::start(addr).expect("can not start exporter");
-    let duration = std::time::Duration::from_millis(10000);
+    let (b_id, b_pw) = match read_credentials(args.credentials_file.as_deref()) {
+        Ok(credentials) => credentials,
+        Err(e) => {
+            error!("invalid B-route credentials: {}", e);
+            return Err(e);
+        }
+    };
 
-    let counter_error_initialize = register_gauge!(
-        "counter_error_initialize",
-        "# of error when try to initialize sensor with PANA"
-    )
-    .expect("can not create gauge counter_error_initialize");
-    let counter_error_sksendto = register_gauge!(
-        "counter_error_sksendto",
-        "# of error when sending data to sensor"
-    )
-    .expect("can not create gauge counter_error_sksendto");
-    let counter_success_initialize = register_gauge!(
-        "counter_success_initialize",
-        "# of times client finished initialization"
-    )
-    .expect("can not create gauge counter_success_initialize");
-    let counter_request_energy = register_gauge!(
-        "counter_request_energy",
-        "# of times client send energy request"
-    )
-    .expect("can not create gauge counter_request_energy");
-    let instantaneous_energy =
-        register_gauge!("instantaneous_energy", "Current Power Consumption in Watt")
-            .expect("can not create gauge instantaneous_energy");
-    let cumulative_energy =
-        register_gauge!("cumulative_energy", "Cumulative Power Consumption in Watt")
-            .expect("can not create gauge cumulative_energy");
+    let addr_raw = args.listen_addr.as_deref().unwrap_or(DEFAULT_LISTEN_ADDR);
+    let addr: SocketAddr = match addr_raw.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("can not parse listen addr {:?}: {:?}", addr_raw, e);
+            std::process::exit(1);
+        }
+    };
+
+    // every metric is labeled with the meter once it is joined
+    let meter_label = MeterLabel::new(prometheus_exporter::prometheus::default_registry().clone());
+    let (exporter, registry) = if disable_prometheus() {
+        info!("Prometheus exporter is disabled");
+        // the metrics are still read by the other outputs and the health check, but not registered to be exported
+        (None, Registry::new())
+    } else {
+        let mut builder = prometheus_exporter::Builder::new(addr);
+        builder.with_registry(meter_label.registry());
+        (
+            Some(builder.start().expect("can not start exporter")),
+            prometheus_exporter::prometheus::default_registry().clone(),
+        )
+    };
+    let duration = poll_interval(args.poll_interval_ms.as_deref());
+    let replay = replay_file().is_some();
+    let max_empty_cycles = max_empty_cycles();
+    let renew_before = pana_renew_before();
+    let history_days = history_days();
+    let output_json = output_json();
+    let sksendto_sec = sksendto_sec();
+    let poll_epcs = poll_epcs();
+    let cumulative_interval = cumulative_interval();
+
+    let metrics = Metrics::register(&registry);
+    let mqtt = MqttPublisher::from_env();
+    let influx = InfluxWriter::from_env(metrics.counter_influx_error.clone());
+    if exporter.is_none() && !output_json && mqtt.is_none() && influx.is_none() {
+        error!("all outputs are disabled, enable OUTPUT_JSON, MQTT_BROKER or INFLUX_URL with DISABLE_PROMETHEUS");
+        return Err("no output is enabled".into());
+    }
 
+    // with DISABLE_PROMETHEUS nothing listens unless the health check is asked for explicitly
+    let health_addr_raw = match std::env::var("HEALTH_LISTEN_ADDR") {
+        Ok(raw) => Some(raw),
+        Err(_) if exporter.is_none() => None,
+        Err(_) => Some(DEFAULT_HEALTH_LISTEN_ADDR.to_string()),
+    };
+    match health_addr_raw {
+        Some(health_addr_raw) => {
+            let health_addr: SocketAddr = match health_addr_raw.parse() {
+                Ok(addr) => addr,
+                Err(e) => {
+                    error!("can not parse health check listen addr {:?}: {:?}", health_addr_raw, e);
+                    std::process::exit(1);
+                }
+            };
+            let health_path =
+                std::env::var("HEALTH_PATH").unwrap_or_else(|_| DEFAULT_HEALTH_PATH.to_string());
+            let status = health::Status {
+                connection_state: metrics.connection_state.clone(),
+                instantaneous_energy: metrics.instantaneous_energy.clone(),
+                cumulative_energy: metrics.cumulative_energy.clone(),
+                last_reading_unixtime: metrics.last_reading_unixtime.clone(),
+            };
+            if let Err(e) = health::start(health_addr, health_path, status) {
+                error!("can not start health check server on {}: {:?}", health_addr, e);
+                return Err(e.into());
+            }
+        }
+        None => info!("health check is disabled along with Prometheus exporter"),
+    }
+
+    let mut poller = Poller {
+        metrics: &metrics,
+        clock: SystemClock,
+        sec: sksendto_sec,
+        output_json,
+        mqtt,
+        influx,
+        last_cumulative_energy: None,
+        last_cumulative_energy_reverse: None,
+        smoothed_energy: smoothing_alpha().map(ExponentialMovingAverage::new),
+        energy_histogram: energy_histogram_buckets().map(|buckets| Metrics::register_energy_histogram(buckets, &registry)),
+        seen_tids: SeenTids::new(SEEN_TIDS_CAPACITY),
+        send_failures: SendFailures::new(channel_rescan_threshold(), channel_rescan_window()),
+    };
+
+    let mut backoff = INITIALIZE_BACKOFF_BASE;
+    let mut ready = false;
+    let mut first_attempt = true;
     loop {
-        let (mut writer, mut receiver, ipv6_addr, handle, cumulative_energy_unit) =
-            match initialize() {
+        if shutdown.load(Ordering::Relaxed) {
+            info!("shutdown requested");
+            return Ok(());
+        }
+        if !first_attempt {
+            metrics.session_reconnect_total.inc();
+        }
+        first_attempt = false;
+
+        let (mut writer, mut receiver, ipv6_addr, handle, meter_info) =
+            match initialize(&b_id, &b_pw, &args, &metrics) {
                 Ok(ipv6_addr) => ipv6_addr,
+                Err(e) if replay => {
+                    error!("unable to initialize smartmeter from the replay file: {:?}", e);
+                    return Err(e.into());
+                }
+                // no meter in range is not a fault of the module, so keep scanning without backing off
+                Err(e @ InitError::ScanEmpty) => {
+                    error!(
+                        "unable to initialize smartmeter, retry in {:?}: {:?}",
+                        INITIALIZE_BACKOFF_BASE, e
+                    );
+                    std::thread::sleep(INITIALIZE_BACKOFF_BASE);
+                    metrics
+                        .counter_error_initialize
+                        .with_label_values(&[e.kind()])
+                        .inc();
+                    continue;
+                }
                 Err(e) => {
-                    error!("unable to initialize smartmeter: {:?}", e);
-                    std::thread::sleep(Duration::from_secs(30));
-                    counter_error_initialize.inc();
+                    error!(
+                        "unable to initialize smartmeter, retry in {:?}: {:?}",
+                        backoff, e
+                    );
+                    std::thread::sleep(backoff);
+                    backoff = (backoff * 2).min(INITIALIZE_BACKOFF_MAX);
+                    metrics
+                        .counter_error_initialize
+                        .with_label_values(&[e.kind()])
+                        .inc();
                     continue;
                 }
             };
-        counter_success_initialize.inc();
+        metrics.counter_success_initialize.inc();
         info!("initialize completed");
+        if !ready {
+            notify_systemd(NotifyState::Ready);
+            ready = true;
+        }
+        backoff = INITIALIZE_BACKOFF_BASE;
+        ConnectionState::Connected.set(&metrics.connection_state);
+        if let Some(digits) = meter_info.digits {
+            metrics.cumulative_energy_digits.set(digits as f64);
+        }
+        metrics
+            .cumulative_energy_unit
+            .set(meter_info.cumulative_energy_unit);
+        metrics.cumulative_energy_raw_unit.set(cumulative_energy_raw_unit(
+            meter_info.coefficient,
+            meter_info.cumulative_energy_unit,
+        ));
+        // the address may change on rejoin, so keep only the current one
+        metrics.meter_address_info.reset();
+        metrics
+            .meter_address_info
+            .with_label_values(&[ipv6_addr.as_str()])
+            .set(1.0);
+        meter_label.set(&ipv6_addr);
+
+        for epc in meter_info.historical_energy_epcs() {
+            for day in 1..=history_days {
+                match collect_historical_energy(
+                    &mut writer,
+                    &mut receiver,
+                    &ipv6_addr,
+                    meter_info.eoj,
+                    sksendto_sec,
+                    epc,
+                    day,
+                ) {
+                    Ok(history) => log_historical_energy(&history, epc, &meter_info),
+                    Err(e) => warn!(
+                        "failed to collect historical energy EPC {:#x} of day {}: {:?}",
+                        epc, day, e
+                    ),
+                }
+            }
+        }
+
+        let requested_epcs = poll_epcs.as_deref().unwrap_or(ENERGY_REQUEST_EPCS);
+        let energy_request_epcs = meter_info.energy_request_epcs(requested_epcs);
+        if energy_request_epcs.len() < requested_epcs.len() {
+            info!("request only EPCs supported by smartmeter: {:x?}", energy_request_epcs);
+        }
+
+        let session_started = Instant::now();
+        let session_lifetime = meter_info.session_lifetime.unwrap_or_else(pana_session_lifetime);
+        // the meter may go quiet while SKSENDTO keeps succeeding, so reconnect if no reading arrives for a while
+        let mut empty_cycles = 0;
+        // None requests the cumulative energy on the first poll
+        let mut last_cumulative_request: Option<Instant> = None;
 
         // main loop
         'main: loop {
-            let _guard = exporter.wait_duration(duration);
-            if let Err(e) = writer.send_command(Command::SendEnergyRequest { ipaddr: &ipv6_addr }) {
-                error!("failed to send command: {:?}", e);
-                counter_error_sksendto.inc();
+            if shutdown.load(Ordering::Relaxed) {
                 break 'main;
             }
-            counter_request_energy.inc();
-            let total_wait_time = std::time::Instant::now();
 
-            // wait response for energy request
-            'wait_response: loop {
-                if total_wait_time.elapsed() > Duration::from_secs(19) {
-                    break 'wait_response;
+            let remaining = session_lifetime.saturating_sub(session_started.elapsed());
+            metrics.pana_session_seconds_remaining.set(remaining.as_secs_f64());
+            metrics
+                .session_uptime_seconds
+                .set(session_started.elapsed().as_secs_f64());
+            if remaining < renew_before {
+                info!("PANA session expires in {:?}, reconnecting beforehand", remaining);
+                metrics.counter_session_renew.inc();
+                if let Err(e) = terminate(&mut writer, &mut receiver) {
+                    warn!("failed to terminate PANA session: {:?}", e);
                 }
+                break 'main;
+            }
 
-                let r = match receiver.recv() {
-                    Ok(r) => r,
-                    Err(e) => {
-                        error!("reader thread closed when they encouter error: {:?}", e);
-                        break 'main;
+            let _guard = match &exporter {
+                Some(exporter) => Some(exporter.wait_duration(duration)),
+                None => {
+                    std::thread::sleep(duration);
+                    None
+                }
+            };
+            let cumulative_due =
+                last_cumulative_request.is_none_or(|t| t.elapsed() >= cumulative_interval);
+            let cycle_epcs = cycle_epcs(&energy_request_epcs, cumulative_due);
+            if cycle_epcs.is_empty() {
+                continue;
+            }
+            if cumulative_due {
+                last_cumulative_request = Some(Instant::now());
+            }
+            let tid = next_tid();
+            let alive = match poller.poll(
+                &mut writer,
+                &mut receiver,
+                &ipv6_addr,
+                &meter_info,
+                tid,
+                &cycle_epcs,
+            ) {
+                PollResult::Response { instantaneous_energy } => {
+                    // without EPC 0xE7 in POLL_EPCS, any response shows the meter is alive
+                    instantaneous_energy
+                        || !cycle_epcs.contains(&EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY)
+                }
+                PollResult::Timeout => false,
+                PollResult::Reconnect => break 'main,
+                PollResult::Rescan => {
+                    metrics.counter_channel_rescan.inc();
+                    FORCE_SCAN.store(true, Ordering::Relaxed);
+                    // leave the PAN cleanly, since the meter may still be reachable
+                    if let Err(e) = terminate(&mut writer, &mut receiver) {
+                        warn!("failed to terminate PANA session: {:?}", e);
                     }
-                };
-                info!("got response {:?}", r);
+                    break 'main;
+                }
+            };
+            if alive {
+                empty_cycles = 0;
+            } else {
+                empty_cycles += 1;
+                if empty_cycles >= max_empty_cycles {
+                    error!("no instantaneous energy for {} cycles, reconnecting", empty_cycles);
+                    metrics.counter_watchdog_reconnect.inc();
+                    break 'main;
+                }
+            }
+        }
+        if shutdown.load(Ordering::Relaxed) {
+            info!("shutdown requested, terminating PANA session");
+            if let Err(e) = terminate(&mut writer, &mut receiver) {
+                warn!("failed to terminate PANA session: {:?}", e);
+            }
+        }
+        drop(writer);
+        drop(receiver);
+        handle.join().expect("failed to join the reader thread");
+        ConnectionState::Disconnected.set(&metrics.connection_state);
+        metrics.session_uptime_seconds.set(0.0);
 
-                match r {
-                    Response::SkSendTo { result: 0x00, .. } => {
-                        debug!("send energy request success");
-                    }
-                    Response::SkSendTo { result: _, .. } => {
-                        warn!("failed to send energy request: {:?}", r);
-                        counter_error_sksendto.inc();
-                        break 'wait_response;
-                    }
-                    Response::ERxUdp {
-                        data:
-                            EchonetLite {
-                                edata:
-                                    EData::EDataFormat1(EDataFormat1 {
-                                        seoj: EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
-                                        props,
-                                        ..
-                                    }),
-                                ..
-                            },
-                        ..
-                    } => {
-                        for prop in props {
-                            match prop {
-                                EDataProperty {
-                                    epc: EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
-                                    pdc: 0x04,
-                                    mut edt,
-                                    ..
-                                } => {
-                                    let power = edt.get_u32();
-                                    instantaneous_energy.set(power as f64);
-                                }
-                                EDataProperty {
-                                    epc: EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION,
-                                    pdc: 0x0b,
-                                    mut edt,
-                                    ..
-                                } => {
-                                    let power = edt.slice(7..11).get_u32();
-                                    cumulative_energy.set((power as f64 )*cumulative_energy_unit);
-                                }
-                                _ => {
-                                    // ignore
-                                }
-                            }
+        // reconnecting only reads the exhausted file again
+        if replay {
+            info!("replay finished");
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus_exporter::prometheus::{Counter, HistogramOpts, Opts};
+    use smartmeter_exporter::parser::parser;
+    use std::cell::Cell;
+    use std::collections::VecDeque;
+    use std::sync::mpsc::channel;
+    use std::sync::Mutex;
+
+    const METER_IPADDR: &str = "FE80:0000:0000:0000:021D:1290:1234:5678";
+
+    #[derive(Debug, Default)]
+    struct FakeSerialState {
+        // replayed one by one for each written command
+        script: VecDeque<Vec<u8>>,
+        pending: VecDeque<u8>,
+        written: Vec<Vec<u8>>,
+    }
+
+    // in-memory serial device which replies the scripted responses
+    #[derive(Debug, Clone, Default)]
+    struct FakeSerial {
+        state: Arc<Mutex<FakeSerialState>>,
+    }
+
+    impl FakeSerial {
+        fn new(script: Vec<Vec<u8>>) -> FakeSerial {
+            FakeSerial {
+                state: Arc::new(Mutex::new(FakeSerialState {
+                    script: script.into(),
+                    ..Default::default()
+                })),
+            }
+        }
+    }
+
+    impl SerialDevice for FakeSerial {
+        fn try_clone_device(&self) -> io::Result<Self> {
+            Ok(self.clone())
+        }
+    }
+
+    impl Read for FakeSerial {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            // return nothing after a while, so that the reader thread notices the writer is dropped
+            for _ in 0..10 {
+                {
+                    let mut state = self.state.lock().unwrap();
+                    if !state.pending.is_empty() {
+                        let n = buf.len().min(state.pending.len());
+                        for (dst, src) in buf.iter_mut().zip(state.pending.drain(..n)) {
+                            *dst = src;
                         }
-                        break 'wait_response;
-                    }
-                    _ => {
-                        // ignore
+                        return Ok(n);
                     }
                 }
+                std::thread::sleep(Duration::from_millis(10));
             }
+            Ok(0)
         }
+    }
+
+    impl Write for FakeSerial {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut state = self.state.lock().unwrap();
+            state.written.push(buf.to_vec());
+            if let Some(response) = state.script.pop_front() {
+                state.pending.extend(response);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn test_gauge() -> Gauge {
+        Gauge::new("test_gauge", "unregistered gauge for tests").unwrap()
+    }
+
+    fn test_gauge_vec(label: &str) -> GaugeVec {
+        GaugeVec::new(Opts::new("test_gauge_vec", "unregistered gauge vec for tests"), &[label]).unwrap()
+    }
+
+    fn test_counter() -> Counter {
+        Counter::new("test_counter", "unregistered counter for tests").unwrap()
+    }
+
+    // the metrics are registered to the global registry, which accepts each name only once
+    fn test_metrics() -> &'static Metrics {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        METRICS.get_or_init(|| Metrics::register(prometheus_exporter::prometheus::default_registry()))
+    }
+
+    fn test_reader_metrics() -> ReaderMetrics {
+        ReaderMetrics {
+            counter_parse_error: test_gauge_vec("kind"),
+            counter_parser_resync: test_gauge(),
+            counter_reader_dropped: test_gauge(),
+            reader_thread_alive: test_gauge(),
+            uart_bytes_read_total: test_counter(),
+            uart_frames_parsed_total: test_counter(),
+        }
+    }
+
+    fn initialize_script() -> Vec<Vec<u8>> {
+        // Get_Res of INITIAL_REQUEST_EPCS
+        let mut frame = b"\x10\x81\x00\x01\x02\x88\x01\x05\xFF\x01\x72\x0A".to_vec();
+        frame.extend_from_slice(b"\x80\x01\x30"); // operating
+        frame.extend_from_slice(b"\x81\x01\x08");
+        frame.extend_from_slice(b"\x88\x01\x42"); // no fault
+        frame.extend_from_slice(b"\x8A\x03\x00\x00\x16");
+        frame.extend_from_slice(b"\x8C\x0CSM-1234     ");
+        frame.extend_from_slice(b"\x8D\x00"); // not supported
+        frame.extend_from_slice(b"\x9F\x06\x05\x80\xD3\xE1\xE7\xE8");
+        frame.extend_from_slice(b"\xD3\x04\x00\x00\x00\x01");
+        frame.extend_from_slice(b"\xD7\x01\x06");
+        frame.extend_from_slice(b"\xE1\x01\x01"); // 0.1 kWh
+        let mut sendto = format!(
+            "SKSENDTO 1 {ip} 0E1A 1 0 000E \r\nEVENT 21 {ip} 00\r\nOK\r\n\r\nERXUDP {ip} FE80:0000:0000:0000:1207:23FF:FEA8:041F 0E1A 0E1A 001D129012345678 1 {:04X} ",
+            frame.len(),
+            ip = METER_IPADDR
+        )
+        .into_bytes();
+        sendto.extend(frame);
+        sendto.extend_from_slice(b"\r\n");
+
+        vec![
+            b"SKRESET\r\nOK\r\n".to_vec(),
+            b"SKVER\r\nEVER 1.2.10\r\nOK\r\n".to_vec(),
+            b"ROPT\r\nOK 00\r\n".to_vec(),
+            b"SKSETRBID 00000000000000000000000000000000\r\nOK\r\n".to_vec(),
+            b"SKSETPWD C 123XXXXXXXXX\r\nOK\r\n".to_vec(),
+            format!(
+                "SKSCAN 2 FFFFFFFF 6 0\r\nOK\r\nEPANDESC\r\n  Channel:21\r\n  Channel Page:09\r\n  Pan ID:8888\r\n  Addr:001D129012345678\r\n  LQI:E1\r\n  Side:0\r\n  PairID:00AXXXXX\r\nEVENT 22 {}\r\n",
+                METER_IPADDR
+            )
+            .into_bytes(),
+            b"SKSREG S2 21\r\nOK\r\n".to_vec(),
+            b"SKSREG S2\r\nESREG 21\r\nOK\r\n".to_vec(),
+            b"SKSREG S3 8888\r\nOK\r\n".to_vec(),
+            format!("SKLL64 001D129012345678\r\n{}\r\n", METER_IPADDR).into_bytes(),
+            format!("SKJOIN {ip}\r\nOK\r\nEVENT 25 {ip}\r\n", ip = METER_IPADDR).into_bytes(),
+            format!(
+                "SKINFO\r\nEINFO {} 001D129012345678 21 8888 FFFE\r\nOK\r\n",
+                METER_IPADDR
+            )
+            .into_bytes(),
+            b"SKSREG S16\r\nESREG 00000384\r\nOK\r\n".to_vec(),
+            sendto,
+        ]
+    }
+
+    #[test]
+    fn test_send_initialize_command_sequence() {
+        let device = FakeSerial::new(initialize_script());
+        let (reader, mut writer) = split_uart(device.clone());
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+        let metrics = test_metrics();
+
+        let (ipaddr, meter_info) = send_initialize_command_sequence(
+            &mut writer,
+            &mut receiver,
+            "00000000000000000000000000000000",
+            "123XXXXXXXXX",
+            metrics,
+        )
+        .unwrap();
         drop(writer);
-        handle.join().expect("failed to join the reader thread");
+        handle.join().unwrap();
+
+        assert_eq!(ipaddr, METER_IPADDR);
+        assert_eq!(meter_info.cumulative_energy_unit, 0.1);
+        assert_eq!(meter_info.coefficient, 1);
+        assert_eq!(meter_info.digits, Some(6));
+        assert_eq!(meter_info.eoj, EOJ_HOUSING_LOW_VOLTAGE_SMART_METER);
+        assert_eq!(meter_info.session_lifetime, Some(Duration::from_secs(900)));
+        assert_eq!(
+            meter_info.energy_request_epcs(ENERGY_REQUEST_EPCS),
+            vec![
+                EpcLowVoltageSmartMeter::OPERATION_STATUS,
+                EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+                EpcLowVoltageSmartMeter::INSTANTANEOUS_CURRENT,
+            ]
+        );
+        assert_eq!(
+            meter_info.historical_energy_epcs(),
+            vec![EpcLowVoltageSmartMeter::HISTORICAL_CUMULATIVE_ENERGY_NORMAL_DIRECTION]
+        );
+        assert_eq!(metrics.current_channel.get(), 33.0);
+        assert_eq!(metrics.meter_operation_status.get(), 1.0);
+        assert_eq!(metrics.meter_location_info.with_label_values(&["08"]).get(), 1.0);
+        assert_eq!(
+            metrics
+                .meter_product_info
+                .with_label_values(&["SM-1234", "unknown"])
+                .get(),
+            1.0
+        );
+        assert_eq!(device.state.lock().unwrap().written[0], b"SKRESET\r\n");
+    }
+
+    #[test]
+    fn test_send_initialize_command_sequence_resend_unit_request() {
+        let mut script = initialize_script();
+        let sendto = script.pop().unwrap();
+        script.push(
+            format!(
+                "SKSENDTO 1 {ip} 0E1A 1 0 000E \r\nEVENT 21 {ip} 01\r\nOK\r\n\r\n",
+                ip = METER_IPADDR
+            )
+            .into_bytes(),
+        );
+        script.push(sendto);
+        let device = FakeSerial::new(script);
+        let (reader, mut writer) = split_uart(device.clone());
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+        let metrics = test_metrics();
+        let retries = metrics.counter_unit_request_retry.get();
+
+        let (_, meter_info) = send_initialize_command_sequence(
+            &mut writer,
+            &mut receiver,
+            "00000000000000000000000000000000",
+            "123XXXXXXXXX",
+            metrics,
+        )
+        .unwrap();
+        drop(writer);
+        handle.join().unwrap();
+
+        assert_eq!(meter_info.cumulative_energy_unit, 0.1);
+        // other tests may initialize in parallel
+        assert!(metrics.counter_unit_request_retry.get() > retries);
+        let written = &device.state.lock().unwrap().written;
+        assert_eq!(written[written.len() - 1], written[written.len() - 2]);
+    }
+
+    #[test]
+    fn test_send_initialize_command_sequence_without_version() {
+        let mut script = initialize_script();
+        script[1] = b"SKVER\r\nFAIL ER04\r\n".to_vec();
+        let device = FakeSerial::new(script);
+        let (reader, mut writer) = split_uart(device.clone());
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+
+        let (ipaddr, _) = send_initialize_command_sequence(
+            &mut writer,
+            &mut receiver,
+            "00000000000000000000000000000000",
+            "123XXXXXXXXX",
+            test_metrics(),
+        )
+        .unwrap();
+        drop(writer);
+        handle.join().unwrap();
+
+        assert_eq!(ipaddr, METER_IPADDR);
+    }
+
+    #[test]
+    fn test_send_initialize_command_sequence_without_ropt() {
+        let mut script = initialize_script();
+        script[2] = b"ROPT\r\nFAIL ER04\r\n".to_vec();
+        script.insert(3, b"WOPT 00\r\nOK\r\n".to_vec());
+        let device = FakeSerial::new(script);
+        let (reader, mut writer) = split_uart(device.clone());
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+
+        let (ipaddr, _) = send_initialize_command_sequence(
+            &mut writer,
+            &mut receiver,
+            "00000000000000000000000000000000",
+            "123XXXXXXXXX",
+            test_metrics(),
+        )
+        .unwrap();
+        drop(writer);
+        handle.join().unwrap();
+
+        assert_eq!(ipaddr, METER_IPADDR);
+        assert_eq!(device.state.lock().unwrap().written[3], b"WOPT 00\r\n".to_vec());
+    }
+
+    #[test]
+    fn test_join_retry_after_join_failed() {
+        let (reader, mut writer) = split_uart(FakeSerial::new(vec![
+            b"SKSREG S2 21\r\nOK\r\n".to_vec(),
+            b"SKSREG S2\r\nESREG 21\r\nOK\r\n".to_vec(),
+            b"SKSREG S3 8888\r\nOK\r\n".to_vec(),
+            format!("SKLL64 001D129012345678\r\n{}\r\n", METER_IPADDR).into_bytes(),
+            format!("SKJOIN {ip}\r\nOK\r\nEVENT 24 {ip}\r\n", ip = METER_IPADDR).into_bytes(),
+            format!("SKTERM\r\nOK\r\nEVENT 27 {ip}\r\n", ip = METER_IPADDR).into_bytes(),
+            format!("SKJOIN {ip}\r\nOK\r\nEVENT 25 {ip}\r\n", ip = METER_IPADDR).into_bytes(),
+        ]));
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+        let pan_desc = PanDesc {
+            channel: 0x21,
+            pan_id: 0x8888,
+            addr: "001D129012345678".to_string(),
+            ..Default::default()
+        };
+        let counter_join_recovery = test_gauge();
+        let init_phase_seconds = test_gauge_vec("phase");
+
+        let ipaddr = join(
+            &mut writer,
+            &mut receiver,
+            &pan_desc,
+            Duration::from_secs(1),
+            false,
+            &counter_join_recovery,
+            &init_phase_seconds,
+        )
+        .unwrap();
+        assert_eq!(ipaddr, METER_IPADDR);
+        assert_eq!(counter_join_recovery.get(), 1.0);
+        for phase in ["sreg", "ll64", "join"] {
+            assert!(init_phase_seconds.with_label_values(&[phase]).get() > 0.0);
+        }
+        drop(writer);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_terminate_without_session() {
+        let device = FakeSerial::new(vec![b"SKTERM\r\nFAIL ER10\r\n".to_vec()]);
+        let (reader, mut writer) = split_uart(device);
+        let (mut receiver, handle) =
+            spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+
+        let start = Instant::now();
+        assert!(terminate(&mut writer, &mut receiver).is_ok());
+        assert!(start.elapsed() < TERMINATE_TIMEOUT);
+        drop(writer);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_join_add_neighbor() {
+        let device = FakeSerial::new(vec![
+            b"SKSREG S2 21\r\nOK\r\n".to_vec(),
+            b"SKSREG S2\r\nESREG 21\r\nOK\r\n".to_vec(),
+            b"SKSREG S3 8888\r\nOK\r\n".to_vec(),
+            format!("SKLL64 001D129012345678\r\n{}\r\n", METER_IPADDR).into_bytes(),
+            format!("SKADDNBR {} 001D129012345678\r\nOK\r\n", METER_IPADDR).into_bytes(),
+            format!("SKJOIN {ip}\r\nOK\r\nEVENT 25 {ip}\r\n", ip = METER_IPADDR).into_bytes(),
+        ]);
+        let (reader, mut writer) = split_uart(device.clone());
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+        let pan_desc = PanDesc {
+            channel: 0x21,
+            pan_id: 0x8888,
+            addr: "001D129012345678".to_string(),
+            ..Default::default()
+        };
+
+        let ipaddr = join(
+            &mut writer,
+            &mut receiver,
+            &pan_desc,
+            Duration::from_secs(1),
+            true,
+            &test_gauge(),
+            &test_gauge_vec("phase"),
+        )
+        .unwrap();
+        assert_eq!(ipaddr, METER_IPADDR);
+        drop(writer);
+        handle.join().unwrap();
+        assert_eq!(
+            device.state.lock().unwrap().written[4],
+            format!("SKADDNBR {} 001D129012345678\r\n", METER_IPADDR).into_bytes()
+        );
+    }
+
+    // a clock which only advances when slept
+    struct FakeClock {
+        start: Instant,
+        slept: Cell<Duration>,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Instant {
+            self.start + self.slept.get()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.slept.set(self.slept.get() + duration);
+        }
+    }
+
+    #[test]
+    fn test_poll() {
+        // Get_Res of 500 W and 1.0 A / 2.0 A
+        let frame = b"\x10\x81\x00\x07\x02\x88\x01\x05\xFF\x01\x72\x02\xE7\x04\x00\x00\x01\xF4\xE8\x04\x00\x0A\x00\x14";
+        let mut response = format!(
+            "SKSENDTO 1 {ip} 0E1A 1 0 000E \r\nEVENT 21 {ip} 00\r\nOK\r\n\r\nERXUDP {ip} FE80:0000:0000:0000:1207:23FF:FEA8:041F 0E1A 0E1A 001D129012345678 1 {:04X} ",
+            frame.len(),
+            ip = METER_IPADDR
+        )
+        .into_bytes();
+        response.extend_from_slice(frame);
+        response.extend_from_slice(b"\r\n");
+        let device = FakeSerial::new(vec![
+            // the first request is lost, and resent after SKSENDTO_RESEND_DELAY
+            format!("SKSENDTO 1 {ip} 0E1A 1 0 000E \r\nEVENT 21 {ip} 01\r\nOK\r\n\r\n", ip = METER_IPADDR)
+                .into_bytes(),
+            response,
+        ]);
+        let (reader, mut writer) = split_uart(device.clone());
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+        let metrics = test_metrics();
+        let clock = FakeClock {
+            start: Instant::now(),
+            slept: Cell::new(Duration::ZERO),
+        };
+        let mut poller = Poller {
+            metrics,
+            clock,
+            sec: SKSENDTO_SEC_ENCRYPTED,
+            output_json: false,
+            mqtt: None,
+            influx: None,
+            last_cumulative_energy: None,
+            last_cumulative_energy_reverse: None,
+            smoothed_energy: Some(ExponentialMovingAverage::new(0.5)),
+            energy_histogram: Some(
+                Histogram::with_opts(
+                    HistogramOpts::new("test_histogram", "unregistered histogram for tests")
+                        .buckets(DEFAULT_ENERGY_HISTOGRAM_BUCKETS.to_vec()),
+                )
+                .unwrap(),
+            ),
+            seen_tids: SeenTids::new(SEEN_TIDS_CAPACITY),
+            send_failures: SendFailures::new(DEFAULT_CHANNEL_RESCAN_THRESHOLD, DEFAULT_CHANNEL_RESCAN_WINDOW),
+        };
+        let meter_info = MeterInfo {
+            cumulative_energy_unit: 0.1,
+            coefficient: 1,
+            digits: Some(6),
+            eoj: EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+            get_property_map: None,
+            session_lifetime: None,
+        };
+
+        let result = poller.poll(
+            &mut writer,
+            &mut receiver,
+            &METER_IPADDR.parse().unwrap(),
+            &meter_info,
+            0x0007,
+            &[
+                EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+                EpcLowVoltageSmartMeter::INSTANTANEOUS_CURRENT,
+            ],
+        );
+        drop(writer);
+        handle.join().unwrap();
+
+        assert_eq!(result, PollResult::Response { instantaneous_energy: true });
+        assert_eq!(poller.clock.slept.get(), SKSENDTO_RESEND_DELAY);
+        assert!(poller.seen_tids.contains(0x0007));
+        assert_eq!(metrics.instantaneous_energy.get(), 500.0);
+        assert_eq!(metrics.instantaneous_energy_smoothed.get(), 500.0);
+        let histogram = poller.energy_histogram.as_ref().unwrap();
+        assert_eq!(histogram.get_sample_count(), 1);
+        assert_eq!(histogram.get_sample_sum(), 500.0);
+        assert_eq!(metrics.instantaneous_current_r.get(), 1.0);
+        assert_eq!(metrics.instantaneous_current_t.get(), 2.0);
+        let power_r = metrics.instantaneous_energy_phase.with_label_values(&["r"]).get();
+        let power_t = metrics.instantaneous_energy_phase.with_label_values(&["t"]).get();
+        assert!((power_r - 500.0 / 3.0).abs() < 1e-9);
+        assert!((power_t - 1000.0 / 3.0).abs() < 1e-9);
+        assert!(metrics.last_reading_unixtime.get() > 0.0);
+        assert_eq!(device.state.lock().unwrap().written.len(), 2);
+    }
+
+    #[test]
+    fn test_reading_json() {
+        let reading = Reading {
+            timestamp: 1700000000.0,
+            instantaneous_watt: 500.0,
+            cumulative_kwh: 1234.5,
+            cumulative_reverse_kwh: 0.0,
+        };
+        assert_eq!(
+            serde_json::to_string(&reading).unwrap(),
+            r#"{"timestamp":1700000000.0,"instantaneous_watt":500.0,"cumulative_kwh":1234.5,"cumulative_reverse_kwh":0.0}"#
+        );
+    }
+
+    #[test]
+    fn test_active_scan_once_without_scan_end() {
+        // EPANDESC is reported but EVENT 0x22 never arrives
+        let (reader, mut writer) = split_uart(FakeSerial::new(vec![
+            b"SKSCAN 2 00000001 0 0\r\nOK\r\nEPANDESC\r\n  Channel:21\r\n  Channel Page:09\r\n  Pan ID:8888\r\n  Addr:001D129012345678\r\n  LQI:E1\r\n  Side:0\r\n  PairID:00AXXXXX\r\n".to_vec(),
+            b"SKSCAN 2 00000001 0 0\r\nOK\r\n".to_vec(),
+        ]));
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+
+        let pandesc =
+            active_scan_once(&mut writer, &mut receiver, 0x1, 0, Duration::from_millis(100)).unwrap();
+        assert_eq!(pandesc.unwrap().addr, "001D129012345678");
+
+        // nothing is found before the deadline
+        let res = active_scan_once(&mut writer, &mut receiver, 0x1, 0, Duration::from_millis(100));
+        assert!(matches!(res, Err(InitError::CommandTimeout { .. })));
+        drop(writer);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_active_scan_empty() {
+        // every scan completes without EPANDESC
+        let script = (SCAN_MIN_DURATION..SCAN_MIN_DURATION + DEFAULT_SCAN_MAX_ATTEMPTS)
+            .map(|duration| {
+                format!(
+                    "SKSCAN 2 FFFFFFFF {:X} 0\r\nOK\r\nEVENT 22 {}\r\n",
+                    duration, METER_IPADDR
+                )
+                .into_bytes()
+            })
+            .collect();
+        let (reader, mut writer) = split_uart(FakeSerial::new(script));
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+
+        let res = active_scan(&mut writer, &mut receiver, Duration::from_millis(100));
+        assert!(matches!(res, Err(InitError::ScanEmpty)));
+        drop(writer);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_cycle_epcs() {
+        assert_eq!(cycle_epcs(ENERGY_REQUEST_EPCS, true), ENERGY_REQUEST_EPCS.to_vec());
+        assert_eq!(
+            cycle_epcs(ENERGY_REQUEST_EPCS, false),
+            vec![
+                EpcLowVoltageSmartMeter::OPERATION_STATUS,
+                EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+                EpcLowVoltageSmartMeter::INSTANTANEOUS_CURRENT,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_env_parse() {
+        // the names are only used by this test, since the environment is shared by the tests running in parallel
+        assert_eq!(env_parse("TEST_ENV_PARSE_UNSET", 3u32), 3);
+        std::env::set_var("TEST_ENV_PARSE_VALID", "5");
+        assert_eq!(env_parse("TEST_ENV_PARSE_VALID", 3u32), 5);
+        assert_eq!(env_parse_valid("TEST_ENV_PARSE_VALID", 3u32, |value| *value < 5), 3);
+        std::env::set_var("TEST_ENV_PARSE_INVALID", "five");
+        assert_eq!(env_parse("TEST_ENV_PARSE_INVALID", 3u32), 3);
+
+        let hex = |raw: &str| u32::from_str_radix(raw, 16).ok();
+        assert_eq!(parse_with("HEX", Some("10"), 1, hex), 16);
+        assert_eq!(parse_with("HEX", Some("xx"), 1, hex), 1);
+        assert_eq!(parse_required::<u32>("SERIAL_BAUD", None).unwrap(), None);
+        assert_eq!(parse_required::<u32>("SERIAL_BAUD", Some("9600")).unwrap(), Some(9600));
+        assert!(parse_required::<u32>("SERIAL_BAUD", Some("fast")).is_err());
+    }
+
+    #[test]
+    fn test_parse_epcs() {
+        assert_eq!(
+            parse_epcs("E7, 0xEA"),
+            Some(vec![
+                EpcLowVoltageSmartMeter::INSTANTANEOUS_ENERGY,
+                EpcLowVoltageSmartMeter::CUMULATIVE_ENERGY_FIXED_TIME_NORMAL_DIRECTION,
+            ])
+        );
+        // not exported
+        assert_eq!(parse_epcs("E7,E0"), None);
+        assert_eq!(parse_epcs("E7,"), None);
+        assert_eq!(parse_epcs("XX"), None);
+    }
+
+    #[test]
+    fn test_parse_buckets() {
+        assert_eq!(parse_buckets("0, 500,1000.5"), Some(vec![0.0, 500.0, 1000.5]));
+        // not increasing
+        assert_eq!(parse_buckets("500,100"), None);
+        assert_eq!(parse_buckets("100,100"), None);
+        assert_eq!(parse_buckets("100,"), None);
+        assert_eq!(parse_buckets("inf"), None);
+    }
+
+    #[test]
+    fn test_parse_eoj() {
+        assert_eq!(parse_eoj("05FF01"), Some(EOJ_MANAGEMENT_CONTROLLER));
+        assert_eq!(
+            parse_eoj("0x05ff02"),
+            Some(Eoj {
+                class_group_code: 0x05,
+                class_code: 0xFF,
+                instance_code: 0x02,
+            })
+        );
+        assert_eq!(parse_eoj("05FF"), None);
+        assert_eq!(parse_eoj("05FFXX"), None);
+    }
+
+    #[test]
+    fn test_parse_pan_desc() {
+        assert_eq!(
+            parse_pan_desc("0x21", "8888", "001d129012345678"),
+            Some(PanDesc {
+                channel: 0x21,
+                pan_id: 0x8888,
+                addr: "001D129012345678".to_string(),
+                ..Default::default()
+            })
+        );
+        assert_eq!(parse_pan_desc("33", "8888", "001D1290123456"), None);
+        assert_eq!(parse_pan_desc("21", "88888", "001D129012345678"), None);
+        assert_eq!(parse_pan_desc("2G", "8888", "001D129012345678"), None);
+    }
+
+    #[test]
+    fn test_link_local_addr() {
+        assert_eq!(
+            link_local_addr("001D129012345678"),
+            Some("FE80:0000:0000:0000:021D:1290:1234:5678".parse().unwrap())
+        );
+        assert_eq!(link_local_addr("001D1290"), None);
+        assert_eq!(link_local_addr("001D12901234567X"), None);
+    }
+
+    #[test]
+    fn test_resolve_link_local_addr() {
+        let (reader, mut writer) = split_uart(FakeSerial::new(vec![
+            format!("SKLL64 001D129012345678\r\n{}\r\n", METER_IPADDR).into_bytes(),
+            format!("{}\r\n", METER_IPADDR).into_bytes(),
+        ]));
+        let (mut receiver, handle) = spawn_reader(reader, None, test_reader_metrics(), DEFAULT_READER_CHANNEL_BOUND, false);
+
+        for _ in 0..2 {
+            let ipaddr = resolve_link_local_addr(
+                &mut writer,
+                &mut receiver,
+                "001D129012345678",
+                Duration::from_secs(1),
+            )
+            .unwrap();
+            assert_eq!(ipaddr, METER_IPADDR);
+        }
+        // no reply
+        let ipaddr = resolve_link_local_addr(
+            &mut writer,
+            &mut receiver,
+            "001D129012345678",
+            Duration::from_millis(100),
+        )
+        .unwrap();
+        assert_eq!(ipaddr, METER_IPADDR);
+        drop(writer);
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_reader_resync() {
+        // the first SKRESET is not followed by OK, and the next line is broken
+        let (reader, mut writer) = split_uart(FakeSerial::new(vec![
+            b"SKRESET\r\nGARBAGE\r\nSKRESET\r\nOK\r\n".to_vec(),
+        ]));
+        let metrics = test_reader_metrics();
+        let (mut receiver, handle) = spawn_reader(reader, None, metrics.clone(), DEFAULT_READER_CHANNEL_BOUND, false);
+        writer.send_command(Command::SkReset).unwrap();
+
+        let r = recv_response_timeout(&mut receiver, Duration::from_secs(1), "SKRESET");
+        assert_eq!(r.unwrap(), Response::SkReset);
+        assert_eq!(metrics.counter_parser_resync.get(), 2.0);
+        assert_eq!(metrics.counter_parse_error.with_label_values(&["error"]).get(), 2.0);
+        assert_eq!(metrics.reader_thread_alive.get(), 1.0);
+        assert_eq!(metrics.uart_bytes_read_total.get(), 31.0);
+        assert_eq!(metrics.uart_frames_parsed_total.get(), 1.0);
+        drop(writer);
+        handle.join().unwrap();
+        assert_eq!(metrics.reader_thread_alive.get(), 0.0);
+    }
+
+    #[test]
+    fn test_reader_drop_when_full() {
+        let (reader, mut writer) = split_uart(FakeSerial::new(vec![
+            b"SKRESET\r\nOK\r\nSKRESET\r\nOK\r\nSKRESET\r\nOK\r\n".to_vec(),
+        ]));
+        let metrics = test_reader_metrics();
+        let (receiver, handle) = spawn_reader(reader, None, metrics.clone(), 1, false);
+        writer.send_command(Command::SkReset).unwrap();
+        // the reader stops as soon as the port is closed, so wait until it parses the replies
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while metrics.uart_frames_parsed_total.get() < 3.0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(writer);
+        // the reader does not wait for the receiver
+        handle.join().unwrap();
+
+        assert_eq!(receiver.iter().collect::<Vec<_>>(), vec![Response::SkReset]);
+        assert_eq!(metrics.uart_frames_parsed_total.get(), 3.0);
+        assert_eq!(metrics.counter_reader_dropped.get(), 2.0);
+    }
+
+    #[test]
+    fn test_recv_response_timeout() {
+        let (sender, mut receiver) = channel();
+        sender.send(Response::UartTimeOut).unwrap();
+        sender.send(Response::SkReset).unwrap();
+        let r = recv_response_timeout(&mut receiver, Duration::from_millis(100), "SKRESET");
+        assert_eq!(r.unwrap(), Response::SkReset);
+
+        let r = recv_response_timeout(&mut receiver, Duration::from_millis(100), "SKRESET");
+        assert_eq!(r.unwrap_err().to_string(), "SKRESET timed out after 100ms");
+
+        sender.send(Response::Fail { code: 4 }).unwrap();
+        let r = recv_response_timeout(&mut receiver, Duration::from_millis(100), "SKSREG");
+        assert_eq!(r.unwrap_err().kind(), "command_rejected");
+        drop(sender);
+    }
+
+    #[test]
+    fn test_recv_meter_reply() {
+        // Set_Res of EPC 0xE5 to the request of `tid`
+        let set_res = |tid: u8| {
+            let frame = [0x10, 0x81, 0x00, tid, 0x02, 0x88, 0x01, 0x05, 0xFF, 0x01, 0x71, 0x01, 0xE5, 0x00];
+            let mut line = format!(
+                "ERXUDP {ip} FE80:0000:0000:0000:1207:23FF:FEA8:041F 0E1A 0E1A 001D129012345678 1 {:04X} ",
+                frame.len(),
+                ip = METER_IPADDR
+            )
+            .into_bytes();
+            line.extend_from_slice(&frame);
+            line.extend_from_slice(b"\r\n");
+            parser(&line).unwrap().1
+        };
+        let (sender, mut receiver) = channel();
+        // the late reply to the previous request is skipped
+        sender.send(set_res(0x01)).unwrap();
+        sender.send(set_res(0x02)).unwrap();
+        let reply = recv_meter_reply(
+            &mut receiver,
+            EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+            0x0002,
+            Duration::from_millis(100),
+            "SetC",
+        )
+        .unwrap();
+        assert_eq!(reply.esv, 0x71);
+
+        let r = recv_meter_reply(
+            &mut receiver,
+            EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+            0x0003,
+            Duration::from_millis(100),
+            "SetC",
+        );
+        assert_eq!(r.unwrap_err().to_string(), "SetC timed out after 100ms");
+
+        sender
+            .send(Response::Event {
+                num: 0x21,
+                sender: METER_IPADDR.parse().unwrap(),
+                param: Some(UDP_SEND_FAILURE),
+            })
+            .unwrap();
+        let r = recv_meter_reply(
+            &mut receiver,
+            EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+            0x0003,
+            Duration::from_millis(100),
+            "SetC",
+        );
+        assert_eq!(r.unwrap_err().to_string(), "SetC could not be sent");
+        drop(sender);
+    }
+
+    #[test]
+    fn test_wait_for_connect() {
+        let (sender, mut receiver) = channel();
+        sender.send(Response::UartTimeOut).unwrap();
+        sender
+            .send(Response::Event {
+                num: 0x25,
+                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
+                param: None,
+            })
+            .unwrap();
+
+        assert!(wait_for_connect(&mut receiver, Duration::from_millis(100)).is_ok());
+    }
+
+    #[test]
+    fn test_wait_for_connect_timeout() {
+        let (sender, mut receiver) = channel();
+        sender.send(Response::UartTimeOut).unwrap();
+        sender
+            .send(Response::Event {
+                num: 0x21,
+                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
+                param: Some(0x00),
+            })
+            .unwrap();
+
+        // the module never yields EVENT 0x24 nor 0x25
+        let res = wait_for_connect(&mut receiver, Duration::from_millis(100));
+        assert_eq!(res.unwrap_err().to_string(), "connect timeout");
+        drop(sender);
     }
 }
@@ -0,0 +1,122 @@
+use std::sync::{Arc, RwLock};
+
+use prometheus_exporter::prometheus::core::{Collector, Desc};
+use prometheus_exporter::prometheus::proto::{LabelPair, MetricFamily};
+use prometheus_exporter::prometheus::Registry;
+
+const METER_LABEL: &str = "meter";
+
+// Serves the metrics of `source` with the `meter` label of the IPv6 address of the connected smartmeter, so that the
+// series of several exporters can be told apart by the meter. The address is only known after the first join and may
+// change on rejoin, so the label is added when the metrics are gathered instead of when they are registered.
+// The metrics are served without the label until the first join.
+#[derive(Clone)]
+pub struct MeterLabel {
+    source: Registry,
+    meter: Arc<RwLock<Option<String>>>,
+}
+
+impl MeterLabel {
+    pub fn new(source: Registry) -> MeterLabel {
+        MeterLabel {
+            source,
+            meter: Arc::new(RwLock::new(None)),
+        }
+    }
+
+    pub fn set(&self, meter: &str) {
+        *self.meter.write().unwrap() = Some(meter.to_string());
+    }
+
+    // the registry to serve, which gathers `source` through this collector
+    pub fn registry(&self) -> Registry {
+        let registry = Registry::new();
+        registry
+            .register(Box::new(self.clone()))
+            .expect("can not register meter label collector");
+        registry
+    }
+}
+
+impl Collector for MeterLabel {
+    // the families are only known when they are gathered from `source`
+    fn desc(&self) -> Vec<&Desc> {
+        Vec::new()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut families = self.source.gather();
+        if let Some(meter) = self.meter.read().unwrap().as_deref() {
+            add_meter_label(&mut families, meter);
+        }
+        families
+    }
+}
+
+// metrics which already have the label, i.e. meter_address_info, are left as they are
+fn add_meter_label(families: &mut [MetricFamily], meter: &str) {
+    for family in families {
+        for metric in family.mut_metric().iter_mut() {
+            if metric.get_label().iter().any(|label| label.get_name() == METER_LABEL) {
+                continue;
+            }
+            let mut label = LabelPair::default();
+            label.set_name(METER_LABEL.to_string());
+            label.set_value(meter.to_string());
+            let mut labels = metric.take_label();
+            labels.push(label);
+            labels.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+            metric.set_label(labels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus_exporter::prometheus::{Gauge, GaugeVec, Opts};
+
+    fn labels(families: &[MetricFamily], name: &str) -> Vec<Vec<(String, String)>> {
+        families
+            .iter()
+            .find(|family| family.get_name() == name)
+            .unwrap()
+            .get_metric()
+            .iter()
+            .map(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_meter_label() {
+        let source = Registry::new();
+        let gauge = Gauge::new("instantaneous_energy", "test").unwrap();
+        source.register(Box::new(gauge.clone())).unwrap();
+        let info = GaugeVec::new(Opts::new("meter_address_info", "test"), &["meter"]).unwrap();
+        source.register(Box::new(info.clone())).unwrap();
+        gauge.set(500.0);
+        info.with_label_values(&["FE80::1"]).set(1.0);
+        let meter_label = MeterLabel::new(source);
+        let registry = meter_label.registry();
+
+        // not joined yet
+        assert_eq!(labels(&registry.gather(), "instantaneous_energy"), vec![Vec::new()]);
+
+        meter_label.set("FE80::2");
+        let families = registry.gather();
+        assert_eq!(
+            labels(&families, "instantaneous_energy"),
+            vec![vec![("meter".to_string(), "FE80::2".to_string())]]
+        );
+        assert_eq!(
+            labels(&families, "meter_address_info"),
+            vec![vec![("meter".to_string(), "FE80::1".to_string())]]
+        );
+    }
+}
@@ -0,0 +1,462 @@
+use std::time::Instant;
+
+use prometheus_exporter::prometheus::{
+    register_counter_with_registry, register_gauge_vec_with_registry, register_gauge_with_registry,
+    register_histogram_with_registry, Counter, Gauge, GaugeVec, Histogram, Registry,
+};
+
+// exported as connection_state gauge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected = 0,
+    Scanning = 1,
+    Joining = 2,
+    Connected = 3,
+}
+
+impl ConnectionState {
+    pub fn set(self, gauge: &Gauge) {
+        gauge.set(self as i32 as f64);
+    }
+}
+
+// sets init_phase_seconds{phase} to the time until it is dropped, so a failed phase is recorded as well
+pub struct PhaseTimer<'a> {
+    gauge: &'a GaugeVec,
+    phase: &'static str,
+    start: Instant,
+}
+
+impl PhaseTimer<'_> {
+    pub fn start<'a>(gauge: &'a GaugeVec, phase: &'static str) -> PhaseTimer<'a> {
+        PhaseTimer {
+            gauge,
+            phase,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for PhaseTimer<'_> {
+    fn drop(&mut self) {
+        self.gauge
+            .with_label_values(&[self.phase])
+            .set(self.start.elapsed().as_secs_f64());
+    }
+}
+
+// the metrics updated by the thread reading the Wi-SUN module
+#[derive(Clone)]
+pub struct ReaderMetrics {
+    pub counter_parse_error: GaugeVec,
+    pub counter_parser_resync: Gauge,
+    pub counter_reader_dropped: Gauge,
+    pub reader_thread_alive: Gauge,
+    pub uart_bytes_read_total: Counter,
+    pub uart_frames_parsed_total: Counter,
+}
+
+pub struct Metrics {
+    pub counter_error_initialize: GaugeVec,
+    pub init_phase_seconds: GaugeVec,
+    pub counter_error_sksendto: Gauge,
+    pub counter_sksendto_retry: Gauge,
+    pub counter_unit_request_retry: Gauge,
+    pub counter_success_initialize: Gauge,
+    pub counter_request_energy: Gauge,
+    pub counter_echonet_sna: Gauge,
+    pub counter_response_timeout: Gauge,
+    pub counter_watchdog_reconnect: Gauge,
+    pub counter_join_recovery: Gauge,
+    pub counter_session_renew: Gauge,
+    pub counter_channel_rescan: Gauge,
+    pub counter_foreign_frame: Gauge,
+    pub counter_duplicate_frame: Gauge,
+    pub counter_parse_error: GaugeVec,
+    pub counter_parser_resync: Gauge,
+    pub counter_reader_dropped: Gauge,
+    pub reader_thread_alive: Gauge,
+    pub uart_bytes_read_total: Counter,
+    pub uart_frames_parsed_total: Counter,
+    pub counter_influx_error: Gauge,
+    pub instantaneous_energy: Gauge,
+    pub instantaneous_energy_smoothed: Gauge,
+    pub cumulative_energy: Gauge,
+    pub cumulative_energy_reverse: Gauge,
+    pub cumulative_energy_total: Counter,
+    pub cumulative_energy_reverse_total: Counter,
+    pub cumulative_energy_digits: Gauge,
+    pub cumulative_energy_raw: Gauge,
+    pub cumulative_energy_reverse_raw: Gauge,
+    pub cumulative_energy_raw_unit: Gauge,
+    pub cumulative_energy_unit: Gauge,
+    pub instantaneous_current_r: Gauge,
+    pub instantaneous_current_t: Gauge,
+    pub instantaneous_energy_phase: GaugeVec,
+    pub last_reading_unixtime: Gauge,
+    pub cumulative_energy_reading_time: Gauge,
+    pub connection_state: Gauge,
+    pub meter_operation_status: Gauge,
+    pub meter_fault: Gauge,
+    pub meter_manufacturer_info: GaugeVec,
+    pub meter_location_info: GaugeVec,
+    pub meter_product_info: GaugeVec,
+    pub meter_address_info: GaugeVec,
+    pub module_firmware_info: GaugeVec,
+    pub current_channel: Gauge,
+    pub current_pan_id: Gauge,
+    pub wisun_rssi: Gauge,
+    pub pana_session_seconds_remaining: Gauge,
+    pub session_reconnect_total: Counter,
+    pub session_uptime_seconds: Gauge,
+}
+
+impl Metrics {
+    pub fn register(registry: &Registry) -> Metrics {
+        Metrics {
+            counter_error_initialize: register_gauge_vec_with_registry!(
+                "counter_error_initialize",
+                "# of error when try to initialize sensor with PANA, labeled by the reason",
+                &["reason"],
+                registry
+            )
+            .expect("can not create gauge counter_error_initialize"),
+            init_phase_seconds: register_gauge_vec_with_registry!(
+                "init_phase_seconds",
+                "Seconds the last initialization spent in each phase",
+                &["phase"],
+                registry
+            )
+            .expect("can not create gauge init_phase_seconds"),
+            counter_error_sksendto: register_gauge_with_registry!(
+                "counter_error_sksendto",
+                "# of error when sending data to sensor",
+                registry
+            )
+            .expect("can not create gauge counter_error_sksendto"),
+            counter_sksendto_retry: register_gauge_with_registry!(
+                "counter_sksendto_retry",
+                "# of times energy request is resent after SKSENDTO failed",
+                registry
+            )
+            .expect("can not create gauge counter_sksendto_retry"),
+            counter_unit_request_retry: register_gauge_with_registry!(
+                "counter_unit_request_retry",
+                "# of resends of the cumulative energy unit request during the initialization",
+                registry
+            )
+            .expect("can not create gauge counter_unit_request_retry"),
+            counter_success_initialize: register_gauge_with_registry!(
+                "counter_success_initialize",
+                "# of times client finished initialization",
+                registry
+            )
+            .expect("can not create gauge counter_success_initialize"),
+            counter_request_energy: register_gauge_with_registry!(
+                "counter_request_energy",
+                "# of times client send energy request",
+                registry
+            )
+            .expect("can not create gauge counter_request_energy"),
+            counter_echonet_sna: register_gauge_with_registry!(
+                "counter_echonet_sna",
+                "# of properties which smartmeter could not serve",
+                registry
+            )
+            .expect("can not create gauge counter_echonet_sna"),
+            counter_response_timeout: register_gauge_with_registry!(
+                "counter_response_timeout",
+                "# of times smartmeter did not respond to energy request in time",
+                registry
+            )
+            .expect("can not create gauge counter_response_timeout"),
+            counter_watchdog_reconnect: register_gauge_with_registry!(
+                "counter_watchdog_reconnect",
+                "# of times the session is reconnected since smartmeter did not report instantaneous energy",
+                registry
+            )
+            .expect("can not create gauge counter_watchdog_reconnect"),
+            counter_join_recovery: register_gauge_with_registry!(
+                "counter_join_recovery",
+                "# of times PANA connection succeeded by terminating the stale session after EVENT 0x24",
+                registry
+            )
+            .expect("can not create gauge counter_join_recovery"),
+            counter_session_renew: register_gauge_with_registry!(
+                "counter_session_renew",
+                "# of times the PANA session is reconnected before it expires",
+                registry
+            )
+            .expect("can not create gauge counter_session_renew"),
+            counter_channel_rescan: register_gauge_with_registry!(
+                "counter_channel_rescan",
+                "# of times the PAN is scanned again since EVENT 0x21 send failures piled up on the channel",
+                registry
+            )
+            .expect("can not create gauge counter_channel_rescan"),
+            counter_foreign_frame: register_gauge_with_registry!(
+                "counter_foreign_frame",
+                "# of frames from ECHONET Lite objects other than the smartmeter which replied on initialization",
+                registry
+            )
+            .expect("can not create gauge counter_foreign_frame"),
+            counter_duplicate_frame: register_gauge_with_registry!(
+                "counter_duplicate_frame",
+                "# of frames dropped since the response with the same TID is already handled",
+                registry
+            )
+            .expect("can not create gauge counter_duplicate_frame"),
+            counter_parse_error: register_gauge_vec_with_registry!(
+                "counter_parse_error",
+                "# of responses from the Wi-SUN module which could not be parsed, labeled by the kind of nom error",
+                &["kind"],
+                registry
+            )
+            .expect("can not create gauge counter_parse_error"),
+            counter_parser_resync: register_gauge_with_registry!(
+                "counter_parser_resync",
+                "# of lines from the Wi-SUN module which are skipped since they could not be parsed",
+                registry
+            )
+            .expect("can not create gauge counter_parser_resync"),
+            counter_reader_dropped: register_gauge_with_registry!(
+                "counter_reader_dropped",
+                "# of responses from the Wi-SUN module which are dropped since the main loop did not take them in time",
+                registry
+            )
+            .expect("can not create gauge counter_reader_dropped"),
+            reader_thread_alive: register_gauge_with_registry!(
+                "reader_thread_alive",
+                "1 while the thread reading the Wi-SUN module is running, 0 after it exits",
+                registry
+            )
+            .expect("can not create gauge reader_thread_alive"),
+            uart_bytes_read_total: register_counter_with_registry!(
+                "uart_bytes_read_total",
+                "# of bytes read from the Wi-SUN module",
+                registry
+            )
+            .expect("can not create counter uart_bytes_read_total"),
+            uart_frames_parsed_total: register_counter_with_registry!(
+                "uart_frames_parsed_total",
+                "# of responses parsed from the bytes read from the Wi-SUN module",
+                registry
+            )
+            .expect("can not create counter uart_frames_parsed_total"),
+            counter_influx_error: register_gauge_with_registry!(
+                "counter_influx_error",
+                "# of readings which could not be written to InfluxDB",
+                registry
+            )
+            .expect("can not create gauge counter_influx_error"),
+            instantaneous_energy: register_gauge_with_registry!(
+                "instantaneous_energy",
+                "Current Power Consumption in Watt",
+                registry
+            )
+            .expect("can not create gauge instantaneous_energy"),
+            instantaneous_energy_smoothed: register_gauge_with_registry!(
+                "instantaneous_energy_smoothed",
+                "Exponential moving average of instantaneous_energy in Watt, updated when INSTANTANEOUS_ENERGY_SMOOTHING is set",
+                registry
+            )
+            .expect("can not create gauge instantaneous_energy_smoothed"),
+            cumulative_energy: register_gauge_with_registry!(
+                "cumulative_energy",
+                "Cumulative energy in kWh reported by the meter, scaled by the coefficient and the unit",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy"),
+            cumulative_energy_reverse: register_gauge_with_registry!(
+                "cumulative_energy_reverse",
+                "Cumulative energy in reverse direction in kWh reported by the meter, scaled by the coefficient and the unit",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy_reverse"),
+            cumulative_energy_total: register_counter_with_registry!(
+                "cumulative_energy_total",
+                "Cumulative energy in kWh increased since the exporter started, which keeps increasing on meter rollover",
+                registry
+            )
+            .expect("can not create counter cumulative_energy_total"),
+            cumulative_energy_reverse_total: register_counter_with_registry!(
+                "cumulative_energy_reverse_total",
+                "Cumulative energy in reverse direction in kWh increased since the exporter started, which keeps increasing on meter rollover",
+                registry
+            )
+            .expect("can not create counter cumulative_energy_reverse_total"),
+            cumulative_energy_digits: register_gauge_with_registry!(
+                "cumulative_energy_digits",
+                "Effective digits of cumulative energy, the counter wraps around at this digits",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy_digits"),
+            cumulative_energy_raw: register_gauge_with_registry!(
+                "cumulative_energy_raw",
+                "Cumulative energy as reported by the meter, multiply by cumulative_energy_raw_unit for kWh",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy_raw"),
+            cumulative_energy_reverse_raw: register_gauge_with_registry!(
+                "cumulative_energy_reverse_raw",
+                "Cumulative energy in reverse direction as reported by the meter, multiply by cumulative_energy_raw_unit for kWh",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy_reverse_raw"),
+            cumulative_energy_raw_unit: register_gauge_with_registry!(
+                "cumulative_energy_raw_unit",
+                "kWh per 1 of the raw cumulative energy (coefficient x unit)",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy_raw_unit"),
+            cumulative_energy_unit: register_gauge_with_registry!(
+                "cumulative_energy_unit",
+                "Unit of cumulative energy in kWh reported by smartmeter (EPC 0xE1)",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy_unit"),
+            instantaneous_current_r: register_gauge_with_registry!(
+                "instantaneous_current_r_ampere",
+                "Current R phase current in Ampere",
+                registry
+            )
+            .expect("can not create gauge instantaneous_current_r_ampere"),
+            instantaneous_current_t: register_gauge_with_registry!(
+                "instantaneous_current_t_ampere",
+                "Current T phase current in Ampere",
+                registry
+            )
+            .expect("can not create gauge instantaneous_current_t_ampere"),
+            instantaneous_energy_phase: register_gauge_vec_with_registry!(
+                "instantaneous_energy_phase",
+                "Instantaneous power of each phase in Watt, estimated from the total and the current of each phase",
+                &["phase"],
+                registry
+            )
+            .expect("can not create gauge instantaneous_energy_phase"),
+            last_reading_unixtime: register_gauge_with_registry!(
+                "last_reading_unixtime",
+                "Unix time when the last energy reading was received from the meter",
+                registry
+            )
+            .expect("can not create gauge last_reading_unixtime"),
+            cumulative_energy_reading_time: register_gauge_with_registry!(
+                "cumulative_energy_reading_time",
+                "Unix time when smartmeter took the fixed-time cumulative energy",
+                registry
+            )
+            .expect("can not create gauge cumulative_energy_reading_time"),
+            connection_state: register_gauge_with_registry!(
+                "connection_state",
+                "PANA connection state (0 = disconnected, 1 = scanning, 2 = joining, 3 = connected)",
+                registry
+            )
+            .expect("can not create gauge connection_state"),
+            meter_operation_status: register_gauge_with_registry!(
+                "meter_operation_status",
+                "Operation status of the smartmeter (1 = on, 0 = off)",
+                registry
+            )
+            .expect("can not create gauge meter_operation_status"),
+            meter_fault: register_gauge_with_registry!(
+                "meter_fault",
+                "Fault status of the smartmeter (1 = fault, 0 = no fault)",
+                registry
+            )
+            .expect("can not create gauge meter_fault"),
+            meter_manufacturer_info: register_gauge_vec_with_registry!(
+                "meter_manufacturer_info",
+                "Manufacturer of the smartmeter",
+                &["code", "name"],
+                registry
+            )
+            .expect("can not create gauge meter_manufacturer_info"),
+            meter_location_info: register_gauge_vec_with_registry!(
+                "meter_location_info",
+                "Installation location code of the smartmeter (EPC 0x81)",
+                &["code"],
+                registry
+            )
+            .expect("can not create gauge meter_location_info"),
+            meter_product_info: register_gauge_vec_with_registry!(
+                "meter_product_info",
+                "Product code (EPC 0x8C) and production number (EPC 0x8D) of the smartmeter",
+                &["product", "serial"],
+                registry
+            )
+            .expect("can not create gauge meter_product_info"),
+            meter_address_info: register_gauge_vec_with_registry!(
+                "meter_address_info",
+                "IPv6 address of the smartmeter the readings are taken from",
+                &["meter"],
+                registry
+            )
+            .expect("can not create gauge meter_address_info"),
+            module_firmware_info: register_gauge_vec_with_registry!(
+                "module_firmware_info",
+                "Firmware version of the Wi-SUN module",
+                &["version"],
+                registry
+            )
+            .expect("can not create gauge module_firmware_info"),
+            current_channel: register_gauge_with_registry!(
+                "current_channel",
+                "Channel of the PANA connection",
+                registry
+            )
+            .expect("can not create gauge current_channel"),
+            current_pan_id: register_gauge_with_registry!(
+                "current_pan_id",
+                "PAN ID of the PANA connection",
+                registry
+            )
+            .expect("can not create gauge current_pan_id"),
+            wisun_rssi: register_gauge_with_registry!(
+                "wisun_rssi",
+                "RSSI of smartmeter in dBm, measured on active scan",
+                registry
+            )
+            .expect("can not create gauge wisun_rssi"),
+            pana_session_seconds_remaining: register_gauge_with_registry!(
+                "pana_session_seconds_remaining",
+                "Seconds until the PANA session expires, approximated from the time since join",
+                registry
+            )
+            .expect("can not create gauge pana_session_seconds_remaining"),
+            session_reconnect_total: register_counter_with_registry!(
+                "session_reconnect_total",
+                "# of times the session is initialized again after the first attempt, whether it failed or was closed",
+                registry
+            )
+            .expect("can not create counter session_reconnect_total"),
+            session_uptime_seconds: register_gauge_with_registry!(
+                "session_uptime_seconds",
+                "Seconds since the current session was initialized, 0 while disconnected",
+                registry
+            )
+            .expect("can not create gauge session_uptime_seconds"),
+        }
+    }
+
+    // only registered when enabled, since most setups are fine with the gauge
+    pub fn register_energy_histogram(buckets: Vec<f64>, registry: &Registry) -> Histogram {
+        register_histogram_with_registry!(
+            "instantaneous_energy_watts",
+            "Distribution of instantaneous_energy in Watt, observed on each reading",
+            buckets,
+            registry
+        )
+        .expect("can not create histogram instantaneous_energy_watts")
+    }
+
+    pub fn reader(&self) -> ReaderMetrics {
+        ReaderMetrics {
+            counter_parse_error: self.counter_parse_error.clone(),
+            counter_parser_resync: self.counter_parser_resync.clone(),
+            counter_reader_dropped: self.counter_reader_dropped.clone(),
+            reader_thread_alive: self.reader_thread_alive.clone(),
+            uart_bytes_read_total: self.uart_bytes_read_total.clone(),
+            uart_frames_parsed_total: self.uart_frames_parsed_total.clone(),
+        }
+    }
+}
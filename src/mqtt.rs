@@ -0,0 +1,129 @@
+use std::thread;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rumqttc::{Client, MqttOptions, QoS};
+use serde_json::json;
+
+use crate::Reading;
+
+const DEFAULT_MQTT_PORT: u16 = 1883;
+const DEFAULT_MQTT_TOPIC_PREFIX: &str = "smartmeter";
+const MQTT_CLIENT_ID: &str = "smartmeter-exporter";
+const MQTT_KEEP_ALIVE: Duration = Duration::from_secs(30);
+// # of requests queued while the broker is unreachable, the rest are dropped
+const MQTT_REQUEST_CAPACITY: usize = 16;
+const MQTT_RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const HOME_ASSISTANT_DISCOVERY_PREFIX: &str = "homeassistant";
+
+// publishes readings as retained messages to MQTT_BROKER.
+// The connection is driven by a background thread, so that an unreachable broker never blocks polling the smartmeter.
+pub struct MqttPublisher {
+    client: Client,
+    topic_prefix: String,
+}
+
+impl MqttPublisher {
+    // returns None if MQTT_BROKER is not set
+    pub fn from_env() -> Option<MqttPublisher> {
+        let broker = std::env::var("MQTT_BROKER").ok()?;
+        let (host, port) = match broker.split_once(':') {
+            Some((host, port)) => match port.parse::<u16>() {
+                Ok(port) => (host.to_string(), port),
+                Err(e) => {
+                    warn!("invalid MQTT_BROKER {:?}, MQTT is disabled: {:?}", broker, e);
+                    return None;
+                }
+            },
+            None => (broker.clone(), DEFAULT_MQTT_PORT),
+        };
+        let topic_prefix = std::env::var("MQTT_TOPIC_PREFIX")
+            .unwrap_or_else(|_| DEFAULT_MQTT_TOPIC_PREFIX.to_string());
+
+        let mut options = MqttOptions::new(MQTT_CLIENT_ID, host, port);
+        options.set_keep_alive(MQTT_KEEP_ALIVE);
+        let (client, mut connection) = Client::new(options, MQTT_REQUEST_CAPACITY);
+
+        thread::spawn(move || {
+            for notification in connection.iter() {
+                match notification {
+                    Ok(event) => debug!("mqtt event {:?}", event),
+                    Err(e) => {
+                        warn!("mqtt connection error, retry in {:?}: {:?}", MQTT_RECONNECT_INTERVAL, e);
+                        thread::sleep(MQTT_RECONNECT_INTERVAL);
+                    }
+                }
+            }
+        });
+
+        info!("publishing readings to MQTT broker {}", broker);
+        let publisher = MqttPublisher {
+            client,
+            topic_prefix,
+        };
+        publisher.publish_discovery();
+        Some(publisher)
+    }
+
+    fn state_topic(&self, name: &str) -> String {
+        format!("{}/{}", self.topic_prefix, name)
+    }
+
+    fn publish(&self, topic: String, payload: String) {
+        if let Err(e) = self
+            .client
+            .try_publish(topic.clone(), QoS::AtLeastOnce, true, payload)
+        {
+            warn!("failed to publish to {}: {:?}", topic, e);
+        }
+    }
+
+    // Home Assistant MQTT discovery, which makes the sensors appear automatically
+    fn publish_discovery(&self) {
+        let sensors = [
+            ("instantaneous_energy", "Instantaneous Power", "W", "power", "measurement"),
+            ("cumulative_energy", "Cumulative Energy", "kWh", "energy", "total_increasing"),
+            (
+                "cumulative_energy_reverse",
+                "Cumulative Energy Reverse",
+                "kWh",
+                "energy",
+                "total_increasing",
+            ),
+        ];
+        for (name, friendly_name, unit, device_class, state_class) in sensors {
+            let unique_id = format!("{}_{}", self.topic_prefix.replace('/', "_"), name);
+            let config = json!({
+                "name": friendly_name,
+                "unique_id": unique_id,
+                "state_topic": self.state_topic(name),
+                "unit_of_measurement": unit,
+                "device_class": device_class,
+                "state_class": state_class,
+                "device": {
+                    "identifiers": [self.topic_prefix],
+                    "name": "Smartmeter",
+                },
+            });
+            self.publish(
+                format!("{}/sensor/{}/config", HOME_ASSISTANT_DISCOVERY_PREFIX, unique_id),
+                config.to_string(),
+            );
+        }
+    }
+
+    pub fn publish_reading(&self, reading: &Reading) {
+        self.publish(
+            self.state_topic("instantaneous_energy"),
+            reading.instantaneous_watt.to_string(),
+        );
+        self.publish(
+            self.state_topic("cumulative_energy"),
+            reading.cumulative_kwh.to_string(),
+        );
+        self.publish(
+            self.state_topic("cumulative_energy_reverse"),
+            reading.cumulative_reverse_kwh.to_string(),
+        );
+    }
+}
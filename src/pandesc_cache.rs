@@ -0,0 +1,69 @@
+use std::error::Error;
+use std::fs;
+
+use crate::parser::PanDesc;
+
+// The PAN descriptor found by the last active scan is saved as "<channel> <pan id> <addr>" in hex,
+// so that the next initialization can join without scanning.
+
+pub fn load(path: &str) -> Result<Option<PanDesc>, Box<dyn Error>> {
+    let raw = match fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let fields: Vec<&str> = raw.split_whitespace().collect();
+    match fields[..] {
+        [channel, pan_id, addr] => Ok(Some(PanDesc {
+            channel: u8::from_str_radix(channel, 16)?,
+            pan_id: u16::from_str_radix(pan_id, 16)?,
+            addr: addr.to_string(),
+            ..Default::default()
+        })),
+        _ => Err(format!("invalid PAN descriptor cache {:?}", raw).into()),
+    }
+}
+
+pub fn save(path: &str, pan_desc: &PanDesc) -> Result<(), Box<dyn Error>> {
+    fs::write(
+        path,
+        format!("{:02X} {:04X} {}\n", pan_desc.channel, pan_desc.pan_id, pan_desc.addr),
+    )?;
+    Ok(())
+}
+
+pub fn remove(path: &str) -> Result<(), Box<dyn Error>> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("pandesc-cache-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let pan_desc = PanDesc {
+            channel: 0x21,
+            pan_id: 0x8888,
+            addr: "001D129012345678".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(load(path).unwrap(), None);
+        save(path, &pan_desc).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), "21 8888 001D129012345678\n");
+        assert_eq!(load(path).unwrap(), Some(pan_desc));
+
+        fs::write(path, "21 8888\n").unwrap();
+        assert!(load(path).is_err());
+
+        remove(path).unwrap();
+        assert_eq!(load(path).unwrap(), None);
+    }
+}
@@ -1,32 +1,104 @@
-// write test module
-
 use std::fmt;
+use std::net::Ipv6Addr;
+use std::ops::Deref;
+use std::str::FromStr;
 
 use bytes::Bytes;
+use log::warn;
 use nom::{
     branch::alt,
     bytes::streaming::{tag, take, take_while1, take_while_m_n},
     character::{
-        is_alphanumeric, is_hex_digit,
-        streaming::{crlf, hex_digit1, space1},
+        is_alphanumeric, is_digit, is_hex_digit,
+        streaming::{crlf, hex_digit1, not_line_ending, space1},
     },
     combinator::{all_consuming, map, map_res, opt, recognize},
     multi::count,
     number::streaming::{be_u16, be_u8},
-    sequence::{delimited, preceded, tuple},
+    sequence::{delimited, preceded, terminated, tuple},
     IResult, ToUsize,
 };
 
-use crate::echonet_lite::{EData, EDataFormat1, EDataProperty, EHd, EchonetLite, Eoj};
+use crate::echonet_lite::{
+    EData, EDataFormat1, EDataProperty, EHd, EchonetLite, Eoj, EpcLowVoltageSmartMeter,
+    EHD1_ECHONET_LITE, EHD2_FORMAT1, EHD2_FORMAT2, EOJ_HOUSING_LOW_VOLTAGE_SMART_METER,
+};
 
 pub type Addr64 = String;
-pub type IpAddr = String;
+
+// IPv6 address in the form the module uses, which is 8 groups of 4 hex digits without abbreviation
+// e.g. FE80:0000:0000:0000:021D:1290:1234:5678
+#[derive(PartialEq, Eq, Clone, Hash)]
+pub struct IpAddr(String);
+
+impl IpAddr {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn to_ipv6(&self) -> Ipv6Addr {
+        // always valid since IpAddr is only made by FromStr, From<Ipv6Addr> and the parser
+        self.0.parse().unwrap()
+    }
+}
+
+impl fmt::Display for IpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl fmt::Debug for IpAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl FromStr for IpAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IpAddr, String> {
+        match all_consuming(parse_ipv6_addr)(s.as_bytes()) {
+            Ok((_, addr)) => Ok(addr),
+            Err(_) => Err(format!("invalid IPv6 address {:?}", s)),
+        }
+    }
+}
+
+impl From<Ipv6Addr> for IpAddr {
+    fn from(addr: Ipv6Addr) -> IpAddr {
+        let s = addr.segments();
+        IpAddr(format!(
+            "{:04X}:{:04X}:{:04X}:{:04X}:{:04X}:{:04X}:{:04X}:{:04X}",
+            s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7]
+        ))
+    }
+}
+
+// commands take the address as &str
+impl Deref for IpAddr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<&str> for IpAddr {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
 #[derive(PartialEq)]
 pub enum Response {
     Ok,
 
     // echo backs
     SkReset,
+    SkTerm,
+    SkVer {
+        version: String,
+    },
     SkSetRbid {
         id: String,
     },
@@ -34,6 +106,12 @@ pub enum Response {
         len: u8,
         pwd: String,
     },
+    SkRopt {
+        mode: u8,
+    },
+    SkWopt {
+        mode: u8,
+    },
     SkScan {
         mode: u8,
         channel_mask: u32,
@@ -44,6 +122,11 @@ pub enum Response {
         sreg: u8,
         val: u32,
     },
+    // reply of SKSREG without value, which reads the register
+    EsReg {
+        sreg: u8,
+        val: u32,
+    },
     SkLl64 {
         addr64: Addr64,
         ipaddr: IpAddr,
@@ -51,6 +134,20 @@ pub enum Response {
     SkJoin {
         ipaddr: IpAddr,
     },
+    SkAddNbr {
+        ipaddr: IpAddr,
+        addr64: Addr64,
+    },
+    // reply of SKLL64 when echo back is disabled (SFE = 0)
+    LinkLocalAddr {
+        ipaddr: IpAddr,
+    },
+    SkInfo {
+        ipaddr: IpAddr,
+        addr64: Addr64,
+        channel: u8,
+        pan_id: u16,
+    },
     SkSendTo {
         handle: u8,
         ipaddr: IpAddr,
@@ -58,7 +155,7 @@ pub enum Response {
         sec: u8,
         reserved: u8,
         datalen: u16,
-        result: u8, // param of Event 0x21
+        result: Option<u8>, // param of Event 0x21, None if the event follows OK as a separate event
     },
 
     // events
@@ -75,9 +172,14 @@ pub enum Response {
         lport: u16,
         senderlla: Addr64,
         secured: u8,
+        side: Option<u8>, // only reported by dual-band modules such as BP35C2
         datalen: u16,
         data: EchonetLite,
     },
+    // the module rejected the command, e.g. ER06 for an invalid argument
+    Fail {
+        code: u8,
+    },
     UartTimeOut,
 }
 
@@ -88,9 +190,25 @@ pub struct PanDesc {
     pub pan_id: u16,
     pub addr: String, // Addr64
     pub lqi: u8,
+    pub side: Option<u8>, // only reported by dual-band modules such as BP35C2
     pub pair_id: String, // char[8]
 }
 
+impl PanDesc {
+    // RSSI in dBm converted from LQI, as described in the BP35A1 command reference
+    pub fn rssi(&self) -> f64 {
+        0.275 * self.lqi as f64 - 104.27
+    }
+
+    // the meter announces the last 8 characters of its B-route ID as PairID
+    pub fn matches_b_id(&self, b_id: &str) -> bool {
+        b_id.len()
+            .checked_sub(self.pair_id.len())
+            .and_then(|start| b_id.get(start..))
+            .is_some_and(|tail| tail.eq_ignore_ascii_case(&self.pair_id))
+    }
+}
+
 impl fmt::Debug for PanDesc {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("PanDesc")
@@ -99,6 +217,7 @@ impl fmt::Debug for PanDesc {
             .field("pan_id", &format_args!("{:#x}", self.pan_id))
             .field("addr", &self.addr)
             .field("lqi", &format_args!("{:#x}", self.lqi))
+            .field("side", &self.side)
             .field("pair_id", &self.pair_id)
             .finish()
     }
@@ -107,14 +226,27 @@ impl fmt::Debug for PanDesc {
 impl fmt::Debug for Response {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Response::Ok {} => f.debug_struct("Ok").finish(),
-            Response::SkReset {} => f.debug_struct("SkReset").finish(),
+            Response::Ok => f.debug_struct("Ok").finish(),
+            Response::SkReset => f.debug_struct("SkReset").finish(),
+            Response::SkTerm => f.debug_struct("SkTerm").finish(),
+            Response::SkVer { version } => f
+                .debug_struct("SkVer")
+                .field("version", &version)
+                .finish(),
             Response::SkSetRbid { id } => f.debug_struct("SkSetRbid").field("id", &id).finish(),
             Response::SkSetPwd { len, pwd } => f
                 .debug_struct("SkSetPwd")
                 .field("len", &len)
                 .field("pwd", &pwd)
                 .finish(),
+            Response::SkRopt { mode } => f
+                .debug_struct("SkRopt")
+                .field("mode", &format_args!("{:#x}", mode))
+                .finish(),
+            Response::SkWopt { mode } => f
+                .debug_struct("SkWopt")
+                .field("mode", &format_args!("{:#x}", mode))
+                .finish(),
             Response::SkScan {
                 mode,
                 channel_mask,
@@ -131,6 +263,11 @@ impl fmt::Debug for Response {
                 .field("sreg", &format_args!("{:#x}", sreg))
                 .field("val", &format_args!("{:#x}", val))
                 .finish(),
+            Response::EsReg { sreg, val } => f
+                .debug_struct("EsReg")
+                .field("sreg", &format_args!("{:#x}", sreg))
+                .field("val", &format_args!("{:#x}", val))
+                .finish(),
             Response::SkLl64 { addr64, ipaddr } => f
                 .debug_struct("SkLl64")
                 .field("addr64", &addr64)
@@ -139,6 +276,11 @@ impl fmt::Debug for Response {
             Response::SkJoin { ipaddr } => {
                 f.debug_struct("SkJoin").field("ipaddr", &ipaddr).finish()
             }
+            Response::SkAddNbr { ipaddr, addr64 } => f
+                .debug_struct("SkAddNbr")
+                .field("ipaddr", &ipaddr)
+                .field("addr64", &addr64)
+                .finish(),
             Response::SkSendTo {
                 handle,
                 ipaddr,
@@ -154,7 +296,23 @@ impl fmt::Debug for Response {
                 .field("port", &port)
                 .field("sec", &format_args!("{:#x}", sec))
                 .field("datalen", &datalen)
-                .field("result", &format_args!("{:#x}", result))
+                .field("result", &result.map(|result| format!("{:#x}", result)))
+                .finish(),
+            Response::LinkLocalAddr { ipaddr } => f
+                .debug_struct("LinkLocalAddr")
+                .field("ipaddr", &ipaddr)
+                .finish(),
+            Response::SkInfo {
+                ipaddr,
+                addr64,
+                channel,
+                pan_id,
+            } => f
+                .debug_struct("SkInfo")
+                .field("ipaddr", &ipaddr)
+                .field("addr64", &addr64)
+                .field("channel", &format_args!("{:#x}", channel))
+                .field("pan_id", &format_args!("{:#x}", pan_id))
                 .finish(),
             Response::Event { num, sender, param } => f
                 .debug_struct("Event")
@@ -173,6 +331,7 @@ impl fmt::Debug for Response {
                 lport,
                 senderlla,
                 secured,
+                side,
                 datalen,
                 data,
             } => f
@@ -183,14 +342,97 @@ impl fmt::Debug for Response {
                 .field("lport", &format_args!("{:#x}",lport))
                 .field("senderlla", &senderlla)
                 .field("secured", &format_args!("{:#x}", secured))
+                .field("side", &side)
                 .field("datalen", &format_args!("{:#x}",datalen))
                 .field("data", &data)
                 .finish(),
+            Response::Fail { code } => f
+                .debug_struct("Fail")
+                .field("code", &format_args!("ER{:02}", code))
+                .finish(),
                 Response::UartTimeOut =>f.debug_struct("timeout").finish()
         }
     }
 }
 
+// one line for the logs, e.g. "ERXUDP from FE80:... tid=0001 seoj=028801 deoj=05FF01 esv=72 INSTANT=450W".
+// The credentials echoed back are masked.
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Ok => f.write_str("OK"),
+            Response::SkReset => f.write_str("SKRESET"),
+            Response::SkTerm => f.write_str("SKTERM"),
+            Response::SkVer { version } => write!(f, "EVER {}", version),
+            Response::SkSetRbid { .. } => f.write_str("SKSETRBID ****"),
+            Response::SkSetPwd { len, .. } => write!(f, "SKSETPWD {:X} ****", len),
+            Response::SkRopt { mode } => write!(f, "ROPT {:02X}", mode),
+            Response::SkWopt { mode } => write!(f, "WOPT {:02X}", mode),
+            Response::SkScan {
+                mode,
+                channel_mask,
+                duration,
+                ..
+            } => write!(f, "SKSCAN {} {:08X} {:X}", mode, channel_mask, duration),
+            Response::SkSreg { sreg, val } => write!(f, "SKSREG S{:X} {:X}", sreg, val),
+            Response::EsReg { sreg, val } => write!(f, "ESREG S{:X}={:08X}", sreg, val),
+            Response::SkLl64 { addr64, ipaddr } => write!(f, "SKLL64 {} -> {}", addr64, ipaddr),
+            Response::SkJoin { ipaddr } => write!(f, "SKJOIN {}", ipaddr),
+            Response::SkAddNbr { ipaddr, addr64 } => write!(f, "SKADDNBR {} {}", ipaddr, addr64),
+            Response::LinkLocalAddr { ipaddr } => write!(f, "link local address {}", ipaddr),
+            Response::SkInfo {
+                ipaddr,
+                addr64,
+                channel,
+                pan_id,
+            } => write!(
+                f,
+                "EINFO {} {} channel={:02X} pan_id={:04X}",
+                ipaddr, addr64, channel, pan_id
+            ),
+            Response::SkSendTo {
+                handle,
+                ipaddr,
+                datalen,
+                result,
+                ..
+            } => {
+                write!(f, "SKSENDTO {} {} {}bytes", handle, ipaddr, datalen)?;
+                match result {
+                    Some(result) => write!(f, " result={:02X}", result),
+                    None => Ok(()),
+                }
+            }
+            Response::Event { num, sender, param } => {
+                write!(f, "EVENT {:02X} {}", num, sender)?;
+                match param {
+                    Some(param) => write!(f, " {:02X}", param),
+                    None => Ok(()),
+                }
+            }
+            Response::EPanDesc(pan_desc) => write!(
+                f,
+                "EPANDESC channel={:02X} pan_id={:04X} addr={} lqi={:02X} pair_id={}",
+                pan_desc.channel, pan_desc.pan_id, pan_desc.addr, pan_desc.lqi, pan_desc.pair_id
+            ),
+            Response::ERxUdp {
+                sender,
+                secured,
+                data,
+                ..
+            } => {
+                write!(f, "ERXUDP from {} ", sender)?;
+                if *secured == 0x00 {
+                    f.write_str("unsecured ")?;
+                }
+                fmt::Display::fmt(data, f)
+            }
+            Response::Fail { code } => write!(f, "FAIL ER{:02}", code),
+            Response::UartTimeOut => f.write_str("UART timeout"),
+        }
+    }
+}
+
 fn parse_ok(input: &[u8]) -> IResult<&[u8], Response> {
     let (input, _) = tuple((tag("OK"), crlf))(input)?;
     Ok((input, Response::Ok))
@@ -203,6 +445,39 @@ fn parse_skreset(input: &[u8]) -> IResult<&[u8], Response> {
     Ok((input, Response::SkReset))
 }
 
+fn parse_skterm(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, _) = tuple((tag("SKTERM"), crlf))(input)?;
+    let (input, _) = parse_ok(input)?;
+
+    Ok((input, Response::SkTerm))
+}
+
+fn parse_skver(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (_, _, _, _, version, _)) = tuple((
+        tag("SKVER"),
+        crlf,
+        tag("EVER"),
+        space1,
+        take_while1(|c: u8| c.is_ascii_graphic()),
+        crlf,
+    ))(input)?;
+    let (input, _) = parse_ok(input)?;
+
+    let version = std::str::from_utf8(version).map_err(|_e| {
+        nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::AlphaNumeric,
+        ))
+    })?;
+
+    Ok((
+        input,
+        Response::SkVer {
+            version: version.to_string(),
+        },
+    ))
+}
+
 fn parse_sksetrbid(input: &[u8]) -> IResult<&[u8], Response> {
     let (input, (_, _, id, _)) =
         tuple((tag("SKSETRBID"), space1, take_while1(is_alphanumeric), crlf))(input)?;
@@ -271,6 +546,31 @@ fn parse_sksetpwd(input: &[u8]) -> IResult<&[u8], Response> {
     ))
 }
 
+fn parse_ropt(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (_, _, _, _, mode, _)) = tuple((
+        tag("ROPT"),
+        crlf,
+        tag("OK"),
+        space1,
+        map_res(hex_digit1, from_hex_u8),
+        crlf,
+    ))(input)?;
+
+    Ok((input, Response::SkRopt { mode }))
+}
+
+fn parse_wopt(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (_, _, mode, _)) = tuple((
+        tag("WOPT"),
+        space1,
+        map_res(hex_digit1, from_hex_u8),
+        crlf,
+    ))(input)?;
+    let (input, _) = parse_ok(input)?;
+
+    Ok((input, Response::SkWopt { mode }))
+}
+
 fn parse_skscan(input: &[u8]) -> IResult<&[u8], Response> {
     let (input, (_, _, mode, _, channel_mask, _, duration, _, reserved, _)) = tuple((
         tag("SKSCAN"),
@@ -323,7 +623,7 @@ fn parse_ipv6_addr(input: &[u8]) -> IResult<&[u8], IpAddr> {
         ))
     })?;
 
-    Ok((input, addr.to_string()))
+    Ok((input, IpAddr(addr.to_string())))
 }
 
 fn parse_event(input: &[u8]) -> IResult<&[u8], Response> {
@@ -343,7 +643,7 @@ fn parse_event(input: &[u8]) -> IResult<&[u8], Response> {
     Ok((input, Response::Event { num, sender, param }))
 }
 fn parse_epandesc(input: &[u8]) -> IResult<&[u8], Response> {
-    let (input, (_, channel, channel_page, pan_id, addr, lqi, _, pair_id)) = tuple((
+    let (input, (_, channel, channel_page, pan_id, addr, lqi, side, pair_id)) = tuple((
         tuple((tag("EPANDESC"), crlf)),
         delimited(tag("  Channel:"), map_res(hex_digit1, from_hex_u8), crlf),
         delimited(
@@ -354,7 +654,8 @@ fn parse_epandesc(input: &[u8]) -> IResult<&[u8], Response> {
         delimited(tag("  Pan ID:"), map_res(hex_digit1, from_hex_u16), crlf),
         delimited(tag("  Addr:"), take_while1(is_alphanumeric), crlf),
         delimited(tag("  LQI:"), map_res(hex_digit1, from_hex_u8), crlf),
-        delimited(tag("  Side:"), map_res(hex_digit1, from_hex_u8), crlf),
+        // Side is only reported by dual-band modules such as BP35C2
+        opt(delimited(tag("  Side:"), map_res(hex_digit1, from_hex_u8), crlf)),
         delimited(tag("  PairID:"), take_while1(is_alphanumeric), crlf),
     ))(input)?;
 
@@ -379,6 +680,7 @@ fn parse_epandesc(input: &[u8]) -> IResult<&[u8], Response> {
             pan_id,
             addr: addr.to_string(),
             lqi,
+            side,
             pair_id: pair_id.to_string(),
         }),
     ))
@@ -398,6 +700,20 @@ fn parse_sksreg(input: &[u8]) -> IResult<&[u8], Response> {
     Ok((input, Response::SkSreg { sreg, val }))
 }
 
+fn parse_esreg(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (_, _, sreg, _)) = tuple((
+        tag("SKSREG"),
+        space1,
+        preceded(tag("S"), map_res(hex_digit1, from_hex_u8)),
+        crlf,
+    ))(input)?;
+    let (input, (_, _, val, _)) =
+        tuple((tag("ESREG"), space1, map_res(hex_digit1, from_hex_u32), crlf))(input)?;
+    let (input, _) = parse_ok(input)?;
+
+    Ok((input, Response::EsReg { sreg, val }))
+}
+
 fn parse_skll64(input: &[u8]) -> IResult<&[u8], Response> {
     let (input, (_, _, addr, _)) =
         tuple((tag("SKLL64"), space1, take_while1(is_alphanumeric), crlf))(input)?;
@@ -419,6 +735,12 @@ fn parse_skll64(input: &[u8]) -> IResult<&[u8], Response> {
     ))
 }
 
+fn parse_link_local_addr(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (ipaddr, _)) = tuple((parse_ipv6_addr, crlf))(input)?;
+
+    Ok((input, Response::LinkLocalAddr { ipaddr }))
+}
+
 fn parse_skjoin(input: &[u8]) -> IResult<&[u8], Response> {
     let (input, (_, _, ipaddr, _)) = tuple((tag("SKJOIN"), space1, parse_ipv6_addr, crlf))(input)?;
     let (input, _) = parse_ok(input)?;
@@ -426,10 +748,94 @@ fn parse_skjoin(input: &[u8]) -> IResult<&[u8], Response> {
     Ok((input, Response::SkJoin { ipaddr }))
 }
 
+fn parse_skaddnbr(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (_, _, ipaddr, _, addr, _)) = tuple((
+        tag("SKADDNBR"),
+        space1,
+        parse_ipv6_addr,
+        space1,
+        take_while1(is_alphanumeric),
+        crlf,
+    ))(input)?;
+    let (input, _) = parse_ok(input)?;
+
+    let addr = std::str::from_utf8(addr).map_err(|_e| {
+        nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::AlphaNumeric,
+        ))
+    })?;
+
+    Ok((
+        input,
+        Response::SkAddNbr {
+            ipaddr,
+            addr64: addr.to_string(),
+        },
+    ))
+}
+
+// FAIL ERnn, which follows the echo back of the command if it is enabled
+fn parse_fail(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (_, _, code, _)) = tuple((
+        opt(terminated(
+            recognize(tuple((alt((tag("SK"), tag("ROPT"), tag("WOPT"))), not_line_ending))),
+            crlf,
+        )),
+        tag("FAIL ER"),
+        map_res(take_while_m_n(2, 2, is_digit), |code| {
+            std::str::from_utf8(code).unwrap().parse::<u8>()
+        }),
+        crlf,
+    ))(input)?;
+
+    Ok((input, Response::Fail { code }))
+}
+
+fn parse_skinfo(input: &[u8]) -> IResult<&[u8], Response> {
+    let (input, (_, _, _, _, ipaddr, _, addr64, _, channel, _, pan_id, _, _, _, _, _)) =
+        tuple((
+            tag("SKINFO"),
+            crlf,
+            tag("EINFO"),
+            space1,
+            parse_ipv6_addr,
+            space1,
+            take_while1(is_alphanumeric),
+            space1,
+            map_res(hex_digit1, from_hex_u8),
+            space1,
+            map_res(hex_digit1, from_hex_u16),
+            space1,
+            hex_digit1, // Addr16
+            opt(space1),
+            opt(hex_digit1), // Side, only reported by dual-band modules
+            crlf,
+        ))(input)?;
+    let (input, _) = parse_ok(input)?;
+
+    let addr64 = std::str::from_utf8(addr64).map_err(|_e| {
+        nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::AlphaNumeric,
+        ))
+    })?;
+
+    Ok((
+        input,
+        Response::SkInfo {
+            ipaddr,
+            addr64: addr64.to_string(),
+            channel,
+            pan_id,
+        },
+    ))
+}
+
 fn parse_erxudp(input: &[u8]) -> IResult<&[u8], Response> {
     let (
         input,
-        (_, _, sender, _, dest, _, rport, _, lport, _, senderlla, _, secured, _, _, _, datalen, _),
+        (_, _, sender, _, dest, _, rport, _, lport, _, senderlla, _, secured, _, side, datalen, _),
     ) = tuple((
         tag("ERXUDP"),
         space1,
@@ -445,8 +851,11 @@ fn parse_erxudp(input: &[u8]) -> IResult<&[u8], Response> {
         space1,
         map_res(hex_digit1, from_hex_u8),
         space1,
-        map_res(hex_digit1, from_hex_u8),
-        space1,
+        // SIDE is a single digit while DATALEN always has 4 digits
+        opt(terminated(
+            map_res(take_while_m_n(1, 1, is_hex_digit), from_hex_u8),
+            space1,
+        )),
         map_res(hex_digit1, from_hex_u16),
         space1,
     ))(input)?;
@@ -458,17 +867,25 @@ fn parse_erxudp(input: &[u8]) -> IResult<&[u8], Response> {
         ))
     })?;
 
-    let (input, data) = take(datalen * 2)(input)?;
-    let (data, ehd) = parse_ehd(data)?;
+    let (input, payload) = parse_erxudp_payload(input, datalen)?;
+    let (input, _) = crlf(input)?;
 
-    let edata = if ehd.ehd1 == 0x10 && ehd.ehd2 == 0x81 {
-        let (_, edata) = all_consuming(parse_edata)(data)?;
+    let (data, ehd) = parse_ehd(&payload).map_err(|_| {
+        nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        ))
+    })?;
 
-        edata
-    } else {
-        EData::InvalidEData(hex::decode(data).unwrap().into())
+    // keep the frame even if it is not a valid ECHONET Lite format 1 frame
+    let edata = match (ehd.ehd1, ehd.ehd2) {
+        (EHD1_ECHONET_LITE, EHD2_FORMAT1) => match all_consuming(parse_edata)(data) {
+            Ok((_, edata)) => edata,
+            _ => EData::InvalidEData(Bytes::copy_from_slice(data)),
+        },
+        (EHD1_ECHONET_LITE, EHD2_FORMAT2) => EData::EDataFormat2(Bytes::copy_from_slice(data)),
+        _ => EData::InvalidEData(Bytes::copy_from_slice(data)),
     };
-    let (input, _) = crlf(input)?;
 
     Ok((
         input,
@@ -479,52 +896,57 @@ fn parse_erxudp(input: &[u8]) -> IResult<&[u8], Response> {
             lport,
             senderlla: addr.to_string(),
             secured,
+            side,
             datalen,
             data: EchonetLite { ehd, edata },
         },
     ))
 }
 
-fn parse_ehd(input: &[u8]) -> IResult<&[u8], EHd> {
-    let (input, ehd1) = take(2u32)(input)?;
-    let ehd1 = from_hex_u8(ehd1).unwrap();
+// WOPT selects whether the module shows ERXUDP payload in binary or in hex ASCII.
+// Binary payload is exactly DATALEN bytes followed by CRLF, while hex ASCII payload has
+// hex digits (never CR) at that position, so the encoding can be detected from there.
+fn parse_erxudp_payload(input: &[u8], datalen: u16) -> IResult<&[u8], Vec<u8>> {
+    let len = datalen as usize;
+    let (_, lookahead) = take(len + 2)(input)?;
 
-    let (input, ehd2) = take(2u32)(input)?;
-    let ehd2 = from_hex_u8(ehd2).unwrap();
+    if &lookahead[len..] == b"\r\n" {
+        let (input, data) = take(len)(input)?;
+        return Ok((input, data.to_vec()));
+    }
+
+    let (input, data) = take(len * 2)(input)?;
+    let data = hex::decode(data).map_err(|_e| {
+        nom::Err::Failure(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::HexDigit,
+        ))
+    })?;
+    Ok((input, data))
+}
 
-    let (input, tid) = take(4u32)(input)?;
-    let tid = from_hex_u16(tid).unwrap();
+fn parse_ehd(input: &[u8]) -> IResult<&[u8], EHd> {
+    let (input, (ehd1, ehd2, tid)) = tuple((be_u8, be_u8, be_u16))(input)?;
 
     let ehd = EHd { ehd1, ehd2, tid };
     Ok((input, ehd))
 }
 
 fn parse_edata_property(input: &[u8]) -> IResult<&[u8], EDataProperty> {
-    let (input, epc) = take(2u32)(input)?;
-    let epc = from_hex_u8(epc).unwrap();
-
-    let (input, pdc) = take(2u32)(input)?;
-    let pdc = from_hex_u8(pdc).unwrap();
-
-    let (input, edt) = take(pdc * 2)(input)?;
+    let (input, (epc, pdc)) = tuple((be_u8, be_u8))(input)?;
+    let (input, edt) = take(pdc)(input)?;
 
     let p = EDataProperty {
         epc,
         pdc,
-        edt: hex::decode(edt).unwrap().into(),
+        edt: Bytes::copy_from_slice(edt),
     };
     Ok((input, p))
 }
 
 fn parse_eoj(input: &[u8]) -> IResult<&[u8], Eoj> {
-    let (input, class_group_code) = take(2u32)(input)?;
-    let class_group_code = from_hex_u8(class_group_code).unwrap();
-
-    let (input, class_code) = take(2u32)(input)?;
-    let class_code = from_hex_u8(class_code).unwrap();
-
-    let (input, instance_code) = take(2u32)(input)?;
-    let instance_code = from_hex_u8(instance_code).unwrap();
+    let (input, (class_group_code, class_code, instance_code)) =
+        tuple((be_u8, be_u8, be_u8))(input)?;
 
     Ok((
         input,
@@ -537,17 +959,25 @@ fn parse_eoj(input: &[u8]) -> IResult<&[u8], Eoj> {
 }
 
 fn parse_edata(input: &[u8]) -> IResult<&[u8], EData> {
-    let (input,seoj) = parse_eoj(input).unwrap();
-    let (input,deoj) = parse_eoj(input).unwrap();
-
-    let (input, esv) = take(2u32)(input)?;
-    let esv = from_hex_u8(esv).unwrap();
-
-    let (input, opc) = take(2u32)(input)?;
-    let opc = from_hex_u8(opc).unwrap();
+    let (input, (seoj, deoj, esv, opc)) = tuple((parse_eoj, parse_eoj, be_u8, be_u8))(input)?;
 
     let (input, props) = count(parse_edata_property, opc as usize)(input)?;
 
+    // the fixed-size properties of the smartmeter are checked here, so that the consumer can decode them as is.
+    // PDC 0 is how the meter tells the property could not be read.
+    if seoj.is_same_class(&EOJ_HOUSING_LOW_VOLTAGE_SMART_METER) {
+        if let Some(prop) = props.iter().find(|prop| {
+            prop.pdc != 0x00
+                && EpcLowVoltageSmartMeter::pdc(prop.epc).is_some_and(|pdc| pdc != prop.pdc)
+        }) {
+            warn!("reject frame with unexpected PDC {:#x} of EPC {:#x}", prop.pdc, prop.epc);
+            return Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Verify,
+            )));
+        }
+    }
+
     Ok((
         input,
         EData::EDataFormat1(EDataFormat1 {
@@ -580,49 +1010,62 @@ fn parse_sksendto(input: &[u8]) -> IResult<&[u8], Response> {
             crlf,
         ))(input)?;
 
-    let (input, event) = parse_event(input)?;
+    // EVENT 0x21 may be reported after OK, which is then parsed as a separate event
+    let (input, event) = opt(parse_event)(input)?;
     let (input, _) = parse_ok(input)?;
     let (input, _) = crlf(input)?;
 
-    if let Response::Event {
-        num: 0x21,
-        param: Some(result),
-        ..
-    } = event
-    {
-        Ok((
-            input,
-            Response::SkSendTo {
-                handle,
-                ipaddr,
-                port,
-                sec,
-                reserved,
-                datalen,
-                result,
-            },
-        ))
-    } else {
-        Err(nom::Err::Failure(nom::error::Error::new(
-            input,
-            nom::error::ErrorKind::Verify,
-        )))
-    }
+    let result = match event {
+        Some(Response::Event {
+            num: 0x21,
+            param: Some(result),
+            ..
+        }) => Some(result),
+        None => None,
+        _ => {
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Verify,
+            )))
+        }
+    };
+
+    Ok((
+        input,
+        Response::SkSendTo {
+            handle,
+            ipaddr,
+            port,
+            sec,
+            reserved,
+            datalen,
+            result,
+        },
+    ))
 }
 
 pub fn parser(input: &[u8]) -> IResult<&[u8], Response> {
     alt((
         parse_skreset,
+        parse_skterm,
+        parse_skver,
         parse_sksetrbid,
         parse_sksetpwd,
+        parse_ropt,
+        parse_wopt,
         parse_skscan,
         parse_event,
         parse_epandesc,
         parse_sksreg,
+        parse_esreg,
         parse_skll64,
+        parse_link_local_addr,
         parse_skjoin,
+        parse_skaddnbr,
+        parse_skinfo,
         parse_erxudp,
         parse_sksendto,
+        parse_fail,
     ))(input)
 }
 
@@ -632,6 +1075,22 @@ mod tests {
     use super::*;
     use std::io::{BufRead, BufReader, Cursor};
 
+    #[test]
+    fn test_ip_addr() {
+        let addr: IpAddr = "FE80:0000:0000:0000:021D:1290:1234:5678".parse().unwrap();
+        assert_eq!(addr.to_string(), "FE80:0000:0000:0000:021D:1290:1234:5678");
+        assert_eq!(addr.to_ipv6(), "fe80::21d:1290:1234:5678".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(IpAddr::from(addr.to_ipv6()), addr);
+
+        // the module prints the address in upper case, but lower case is also accepted
+        let lower: IpAddr = "fe80:0000:0000:0000:021d:1290:1234:5678".parse().unwrap();
+        assert_eq!(lower.to_ipv6(), addr.to_ipv6());
+
+        assert!("fe80::21d:1290:1234:5678".parse::<IpAddr>().is_err());
+        assert!("FE80:0000:0000:0000:021D:1290:1234".parse::<IpAddr>().is_err());
+        assert!("FE80:0000:0000:0000:021D:1290:1234:5678 ".parse::<IpAddr>().is_err());
+    }
+
     #[test]
     fn test_parse_cursor() -> Result<(), Box<dyn std::error::Error>> {
         let c = Cursor::new(b"AAA\r\nBBB\r\n");
@@ -661,6 +1120,25 @@ mod tests {
         assert_eq!(response, Response::SkReset);
     }
 
+    #[test]
+    fn test_parse_skterm() {
+        let (rest, response) = parser(&b"SKTERM\r\nOK\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(response, Response::SkTerm);
+    }
+
+    #[test]
+    fn test_parse_skver() {
+        let (rest, response) = parser(&b"SKVER\r\nEVER 1.2.10\r\nOK\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(
+            response,
+            Response::SkVer {
+                version: "1.2.10".to_string()
+            }
+        );
+    }
+
     #[test]
     fn test_parse_sksetrbid() {
         let (rest, response) =
@@ -739,9 +1217,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_ropt() {
+        let (rest, response) = parser(&b"ROPT\r\nOK 01\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(response, Response::SkRopt { mode: 0x01 });
+    }
+
+    #[test]
+    fn test_parse_wopt() {
+        let (rest, response) = parser(&b"WOPT 00\r\nOK\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(response, Response::SkWopt { mode: 0x00 });
+    }
+
     #[test]
     fn test_parse_skscan() {
-        let (rest, response) = parser(&b"SKSCAN 2 FFFFFFFF 6\r\nOK\r\n"[..]).unwrap();
+        let (rest, response) = parser(&b"SKSCAN 2 FFFFFFFF 6 0\r\nOK\r\n"[..]).unwrap();
         assert_eq!(rest, &b""[..]);
         assert_eq!(
             response,
@@ -749,6 +1241,7 @@ mod tests {
                 mode: 2,
                 channel_mask: 0xffffffff,
                 duration: 6,
+                reserved: 0,
             }
         );
     }
@@ -758,7 +1251,13 @@ mod tests {
         let (rest, addr) =
             parse_ipv6_addr(&b"FE80:0000:0000:0000:0123:4567:89ab:cdef\r\n"[..]).unwrap();
         assert_eq!(rest, &b"\r\n"[..]); // need delimiter because streaming parser read as much u16 hex text as possible
-        assert_eq!(addr, "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string());
+        assert_eq!(addr, "FE80:0000:0000:0000:0123:4567:89ab:cdef");
+    }
+
+    #[test]
+    fn test_parse_event_incomplete() {
+        let res = parser(&b"EVENT 21 FE80:0000:0000"[..]);
+        assert!(matches!(res, Err(nom::Err::Incomplete(_))));
     }
 
     #[test]
@@ -770,7 +1269,7 @@ mod tests {
             response,
             Response::Event {
                 num: 0x20,
-                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
+                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
                 param: None,
             }
         );
@@ -782,7 +1281,7 @@ mod tests {
             response,
             Response::Event {
                 num: 0x22,
-                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
+                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
                 param: None,
             }
         );
@@ -794,7 +1293,7 @@ mod tests {
             response,
             Response::Event {
                 num: 0x21,
-                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
+                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
                 param: Some(0x02),
             }
         );
@@ -812,9 +1311,51 @@ mod tests {
                 pan_id: 0x8888,
                 addr: "001D129012345678".to_string(),
                 lqi: 0xe1,
+                side: None,
                 pair_id: "00AXXXXX".to_string(),
             })
         );
+
+        let (rest, epandesc) = parser(&b"EPANDESC\r\n  Channel:3B\r\n  Channel Page:09\r\n  Pan ID:1234\r\n  Addr:001D129012345678\r\n  LQI:5A\r\n  Side:1\r\n  PairID:0012ABCD\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(
+            epandesc,
+            Response::EPanDesc(PanDesc {
+                channel: 0x3b,
+                channel_page: 0x09,
+                pan_id: 0x1234,
+                addr: "001D129012345678".to_string(),
+                lqi: 0x5a,
+                side: Some(1),
+                pair_id: "0012ABCD".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_pandesc_matches_b_id() {
+        let pandesc = PanDesc {
+            pair_id: "0012ABCD".to_string(),
+            ..Default::default()
+        };
+        assert!(pandesc.matches_b_id("0000000000000000000000000012abcd"));
+        assert!(!pandesc.matches_b_id("00000000000000000000000000000000"));
+        assert!(!pandesc.matches_b_id("ABCD"));
+    }
+
+    #[test]
+    fn test_pandesc_rssi() {
+        let pandesc = PanDesc {
+            lqi: 0xe1,
+            ..Default::default()
+        };
+        assert!((pandesc.rssi() - -42.395).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_parse_epandesc_incomplete() {
+        let res = parser(&b"EPANDESC\r\n  Channel:21\r\n  Channel Page:09\r\n"[..]);
+        assert!(matches!(res, Err(nom::Err::Incomplete(_))));
     }
 
     #[test]
@@ -834,6 +1375,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_esreg() {
+        let (rest, response) = parser(&b"SKSREG S2\r\nESREG 21\r\nOK\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(response, Response::EsReg { sreg: 2, val: 0x21 });
+
+        assert!(matches!(
+            parser(&b"SKSREG S2\r\nESREG"[..]),
+            Err(nom::Err::Incomplete(_))
+        ));
+    }
+
     #[test]
     fn test_parse_skll64() {
         let (rest, response) =
@@ -844,7 +1397,20 @@ mod tests {
             response,
             Response::SkLl64 {
                 addr64: "0123456789ABCDEF".to_string(),
-                ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
+                ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_link_local_addr() {
+        let (rest, response) =
+            parser(&b"FE80:0000:0000:0000:0123:4567:89ab:cdef\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(
+            response,
+            Response::LinkLocalAddr {
+                ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
             }
         );
     }
@@ -857,7 +1423,61 @@ mod tests {
         assert_eq!(
             response,
             Response::SkJoin {
-                ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
+                ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_skaddnbr() {
+        let (rest, response) = parser(
+            &b"SKADDNBR FE80:0000:0000:0000:021D:1290:1234:5678 001D129012345678\r\nOK\r\n"[..],
+        )
+        .unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(
+            response,
+            Response::SkAddNbr {
+                ipaddr: "FE80:0000:0000:0000:021D:1290:1234:5678".parse().unwrap(),
+                addr64: "001D129012345678".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_fail() {
+        let cases: [(&[u8], u8); 4] = [
+            (b"FAIL ER04\r\n", 4),
+            (b"SKSETRBID 0000\r\nFAIL ER05\r\n", 5),
+            (b"SKSREG S2 FFF\r\nFAIL ER06\r\n", 6),
+            (b"SKJOIN FE80:0000:0000:0000:021D:1290:1234:5678\r\nFAIL ER10\r\n", 10),
+        ];
+        for (input, code) in cases {
+            let (rest, response) = parser(input).unwrap();
+            assert_eq!(rest, &b""[..]);
+            assert_eq!(response, Response::Fail { code });
+        }
+        assert_eq!(format!("{:?}", Response::Fail { code: 4 }), "Fail { code: ER04 }");
+
+        // the echo back of another line is not taken as the failed command
+        assert!(parser(&b"GARBAGE\r\nFAIL ER04\r\n"[..]).is_err());
+        assert!(matches!(parser(&b"FAIL ER0"[..]), Err(nom::Err::Incomplete(_))));
+    }
+
+    #[test]
+    fn test_parse_skinfo() {
+        let (rest, response) = parser(
+            &b"SKINFO\r\nEINFO FE80:0000:0000:0000:021D:1290:1234:5678 001D129012345678 21 8888 FFFE\r\nOK\r\n"[..],
+        )
+        .unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(
+            response,
+            Response::SkInfo {
+                ipaddr: "FE80:0000:0000:0000:021D:1290:1234:5678".parse().unwrap(),
+                addr64: "001D129012345678".to_string(),
+                channel: 0x21,
+                pan_id: 0x8888,
             }
         );
     }
@@ -918,6 +1538,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_edata_unexpected_pdc() {
+        // EPC 0xE7 of the smartmeter always has 4 bytes
+        assert!(parse_edata(&b"\x02\x88\x01\x05\xff\x01\x72\x01\xe7\x02\x01\xa8"[..]).is_err());
+        assert!(parse_edata(&b"\x02\x88\x01\x05\xff\x01\x52\x01\xe7\x00"[..]).is_ok());
+        // the EPC means another property for the other classes
+        assert!(parse_edata(&b"\x05\xff\x01\x02\x88\x01\x72\x01\xe7\x02\x01\xa8"[..]).is_ok());
+
+        let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 0010 \x10\x81\0\x05\x02\x88\x01\x05\xff\x01\x72\x01\xe7\x02\x01\xa8\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert!(matches!(
+            response,
+            Response::ERxUdp {
+                data: EchonetLite {
+                    edata: EData::InvalidEData(_),
+                    ..
+                },
+                ..
+            }
+        ));
+    }
+
     #[test]
     fn test_parse_erxudp() {
         let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 0012 \x10\x81\0\x01\x02\x88\x01\x05\xff\x01r\x01\xe7\x04\0\0\x01\xa8\r\n"[..]).unwrap();
@@ -926,12 +1568,162 @@ mod tests {
         assert_eq!(
             response,
             Response::ERxUdp {
-                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
-                dest: "FE80:0000:0000:0000:3210:7654:ba98:fedc".to_string(),
+                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
+                dest: "FE80:0000:0000:0000:3210:7654:ba98:fedc".parse().unwrap(),
+                rport: 0xe1a,
+                lport: 0xe1a,
+                senderlla: "001D129012345678".to_string(),
+                secured: 0x01,
+                side: None,
+                datalen: 0x012,
+                data: EchonetLite {
+                    ehd: EHd {
+                        ehd1: 0x10,
+                        ehd2: 0x81,
+                        tid: 0x0001,
+                    },
+                    edata: EData::EDataFormat1(EDataFormat1 {
+                        seoj: Eoj {
+                            class_group_code: 0x02,
+                            class_code: 0x88,
+                            instance_code: 0x01,
+                        },
+                        deoj: Eoj {
+                            class_group_code: 0x05,
+                            class_code: 0xff,
+                            instance_code: 0x01,
+                        },
+                        esv: 0x72,
+                        opc: 0x01,
+                        props: vec![EDataProperty {
+                            epc: 0xe7,
+                            pdc: 0x04,
+                            edt: Bytes::from_static(b"\0\0\x01\xa8"),
+                        }],
+                    })
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_erxudp_energy() {
+        // Get_Res of instantaneous energy (424W) and cumulative energy at 2023-04-15 12:30:00 (7777)
+        let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 001F \x10\x81\0\x05\x02\x88\x01\x05\xff\x01\x72\x02\xe7\x04\0\0\x01\xa8\xea\x0b\x07\xe7\x04\x0f\x0c\x1e\0\0\0\x1e\x61\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+
+        let props = match response {
+            Response::ERxUdp {
+                secured: 0x01,
+                datalen: 0x1f,
+                data:
+                    EchonetLite {
+                        ehd: EHd { tid: 0x0005, .. },
+                        edata: EData::EDataFormat1(EDataFormat1 { esv: 0x72, opc: 0x02, props, .. }),
+                    },
+                ..
+            } => props,
+            r => panic!("unexpected response {:?}", r),
+        };
+        assert_eq!(
+            props,
+            vec![
+                EDataProperty {
+                    epc: 0xe7,
+                    pdc: 0x04,
+                    edt: Bytes::from_static(b"\0\0\x01\xa8"),
+                },
+                EDataProperty {
+                    epc: 0xea,
+                    pdc: 0x0b,
+                    edt: Bytes::from_static(b"\x07\xe7\x04\x0f\x0c\x1e\0\0\0\x1e\x61"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_response_display() {
+        let (_, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 0012 \x10\x81\0\x05\x02\x88\x01\x05\xff\x01\x72\x01\xe7\x04\0\0\x01\xc2\r\n"[..]).unwrap();
+        assert_eq!(
+            response.to_string(),
+            "ERXUDP from FE80:0000:0000:0000:0123:4567:89ab:cdef tid=0005 seoj=028801 deoj=05FF01 esv=72 INSTANT=450W"
+        );
+
+        let (_, response) = parser(&b"SKSETPWD C 123XXXXXXXXX\r\nOK\r\n"[..]).unwrap();
+        assert_eq!(response.to_string(), "SKSETPWD C ****");
+        assert_eq!(Response::Fail { code: 4 }.to_string(), "FAIL ER04");
+    }
+
+    #[test]
+    fn test_parse_erxudp_three_phase() {
+        // Get_Res from a three-phase 3-wire meter: instantaneous energy (1200W) and current of R phase (12.3A) and T phase (8.5A)
+        let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 0018 \x10\x81\0\x06\x02\x88\x01\x05\xff\x01\x72\x02\xe7\x04\0\0\x04\xb0\xe8\x04\0\x7b\0\x55\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+
+        let props = match response {
+            Response::ERxUdp {
+                data:
+                    EchonetLite {
+                        edata: EData::EDataFormat1(EDataFormat1 { esv: 0x72, opc: 0x02, props, .. }),
+                        ..
+                    },
+                ..
+            } => props,
+            r => panic!("unexpected response {:?}", r),
+        };
+        assert_eq!(
+            props,
+            vec![
+                EDataProperty {
+                    epc: 0xe7,
+                    pdc: 0x04,
+                    edt: Bytes::from_static(b"\0\0\x04\xb0"),
+                },
+                EDataProperty {
+                    epc: 0xe8,
+                    pdc: 0x04,
+                    edt: Bytes::from_static(b"\0\x7b\0\x55"),
+                },
+            ]
+        );
+        assert_eq!(
+            crate::echonet_lite::instantaneous_current(&props[1].edt),
+            Some((12.3, Some(8.5)))
+        );
+    }
+
+    #[test]
+    fn test_parse_erxudp_format2() {
+        let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 0007 \x10\x82\0\x01\xde\xad\xbe\r\n"[..]).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert!(matches!(
+            response,
+            Response::ERxUdp {
+                data: EchonetLite {
+                    ehd: EHd { ehd2: EHD2_FORMAT2, .. },
+                    edata: EData::EDataFormat2(ref data),
+                },
+                ..
+            } if data == &Bytes::from_static(b"\xde\xad\xbe")
+        ));
+    }
+
+    #[test]
+    fn test_parse_erxudp_hex_ascii() {
+        let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 0012 1081000102880105FF017201E704000001A8\r\n"[..]).unwrap();
+
+        assert_eq!(rest, &b""[..]);
+        assert_eq!(
+            response,
+            Response::ERxUdp {
+                sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
+                dest: "FE80:0000:0000:0000:3210:7654:ba98:fedc".parse().unwrap(),
                 rport: 0xe1a,
                 lport: 0xe1a,
                 senderlla: "001D129012345678".to_string(),
                 secured: 0x01,
+                side: None,
                 datalen: 0x012,
                 data: EchonetLite {
                     ehd: EHd {
@@ -963,18 +1755,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_erxudp_incomplete_payload() {
+        let res = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 0E1A 0E1A 001D129012345678 1 0012 \x10\x81\0\x01"[..]);
+        assert!(matches!(res, Err(nom::Err::Incomplete(_))));
+    }
+
     #[test]
     fn test_parse_erxudp_invalid_frame() {
         let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0280:8700:3025:9813 FE80:0000:0000:0000:1207:23FF:FEA8:041F 02CC 02CC 0080870030259813 0 0 0028 00000028C00000022B8F33228E3BEA4800060000000400000000000500030000000400000000000C\r\n"[..]).unwrap();
 
         assert_eq!(rest, &b""[..]);
         assert_eq!(response, Response::ERxUdp {
-            sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
-            dest: "FE80:0000:0000:0000:3210:7654:ba98:fedc".to_string(),
+            sender: "FE80:0000:0000:0000:0280:8700:3025:9813".parse().unwrap(),
+            dest: "FE80:0000:0000:0000:1207:23FF:FEA8:041F".parse().unwrap(),
             rport: 0x2cc,
             lport: 0x2cc,
-            senderlla: "001D129012345678".to_string(),
+            senderlla: "0080870030259813".to_string(),
             secured: 0x00,
+            side: Some(0x00),
             datalen: 0x028,
             data: EchonetLite {
                 ehd: EHd {
@@ -982,19 +1781,20 @@ mod tests {
                     ehd2: 0x00,
                     tid: 0x0028,
                 },
-                edata: EData::InvalidEData(Bytes::from_static(b"\xc0\0\0\x02\x06\x04S\x07\x8d\xd5a\xbf\0\x06\0\0\0\x04\0\0\0\0\0\x05\0\x03\0\0\0\x04\0\0\0\0\0\x0c"))
+                edata: EData::InvalidEData(Bytes::from_static(b"\xc0\0\0\x02\x2b\x8f\x33\x22\x8e\x3b\xea\x48\0\x06\0\0\0\x04\0\0\0\0\0\x05\0\x03\0\0\0\x04\0\0\0\0\0\x0c"))
             }
         });
 
         let (rest, response) = parser(&b"ERXUDP FE80:0000:0000:0000:0123:4567:89ab:cdef FE80:0000:0000:0000:3210:7654:ba98:fedc 02CC 02CC 001D129012345678 0 0058 \0\0\0X\xa0\0\0\x02\x06\x04S\x07\x8d\xd5a\xc2\0\x07\0\0\0\x04\0\0\0\0\0\0\0\x02\0\0\0\x04\0\0\x03\xb5\0\x04\0\x04\0\0\0\x04\0\0\0\0\x07\x01\0\x08\0\0\0\x04\0\0\0\x01Q\x80\0\x01\0\0\0\x10\0\0\x13v\x01$1\x1c\x90\xd3T\xb6p 83\xee\xe7\r\n"[..]).unwrap();
         assert_eq!(rest, &b""[..]);
         assert_eq!(response, Response::ERxUdp {
-            sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
-            dest: "FE80:0000:0000:0000:3210:7654:ba98:fedc".to_string(),
+            sender: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
+            dest: "FE80:0000:0000:0000:3210:7654:ba98:fedc".parse().unwrap(),
             rport: 0x2cc,
             lport: 0x2cc,
             senderlla: "001D129012345678".to_string(),
             secured: 0x00,
+            side: None,
             datalen: 0x058,
             data: EchonetLite {
                 ehd: EHd {
@@ -1009,18 +1809,32 @@ mod tests {
 
     #[test]
     fn test_parse_sksendto() {
-        let (rest, response) = parser(&b"SKSENDTO 1 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 1 000e \r\nEVENT 21 FE80:0000:0000:0000:0123:4567:89ab:cdef 00\r\nOK\r\n\r\n"[..]).unwrap();
+        let (rest, response) = parser(&b"SKSENDTO 1 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 1 0 000e \r\nEVENT 21 FE80:0000:0000:0000:0123:4567:89ab:cdef 00\r\nOK\r\n\r\n"[..]).unwrap();
         assert_eq!(rest, &b""[..]);
         assert_eq!(
             response,
             Response::SkSendTo {
                 handle: 0x1,
-                ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef".to_string(),
+                ipaddr: "FE80:0000:0000:0000:0123:4567:89ab:cdef".parse().unwrap(),
                 port: 0xe1a,
                 sec: 0x1,
+                reserved: 0x0,
                 datalen: 0x0e,
-                result: 0x00,
+                result: Some(0x00),
             }
         );
+
+        let (rest, response) = parser(&b"SKSENDTO 1 FE80:0000:0000:0000:0123:4567:89ab:cdef 0E1A 1 0 000e \r\nOK\r\n\r\nEVENT 21 FE80:0000:0000:0000:0123:4567:89ab:cdef 01\r\n"[..]).unwrap();
+        assert!(matches!(response, Response::SkSendTo { result: None, .. }));
+        let (rest, response) = parser(rest).unwrap();
+        assert_eq!(rest, &b""[..]);
+        assert!(matches!(
+            response,
+            Response::Event {
+                num: 0x21,
+                param: Some(0x01),
+                ..
+            }
+        ));
     }
 }
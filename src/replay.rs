@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::capture;
+use crate::SerialDevice;
+
+#[derive(Debug)]
+struct ReplayState {
+    lines: BufReader<File>,
+    // the rest of the current chunk which did not fit in the buffer
+    pending: VecDeque<u8>,
+}
+
+// A capture of the UART recorded with CAPTURE_FILE, which stands in for the Wi-SUN module when REPLAY_FILE is set.
+// The responses are read from the file regardless of the commands, and the commands are discarded.
+#[derive(Debug, Clone)]
+pub struct ReplayFile {
+    state: Arc<Mutex<ReplayState>>,
+}
+
+impl ReplayFile {
+    pub fn open(path: &str) -> io::Result<ReplayFile> {
+        Ok(ReplayFile {
+            state: Arc::new(Mutex::new(ReplayState {
+                lines: BufReader::new(File::open(path)?),
+                pending: VecDeque::new(),
+            })),
+        })
+    }
+}
+
+impl SerialDevice for ReplayFile {
+    fn try_clone_device(&self) -> io::Result<Self> {
+        Ok(self.clone())
+    }
+}
+
+impl Read for ReplayFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        // each chunk is returned as it was read from the UART
+        while state.pending.is_empty() {
+            let mut line = String::new();
+            if state.lines.read_line(&mut line)? == 0 {
+                // unlike the serial port, the file never yields more data, so let the reader thread finish
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "replay file is exhausted",
+                ));
+            }
+            let chunk = capture::parse_line(&line).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid line in replay file {:?}", line),
+                )
+            })?;
+            state.pending.extend(chunk);
+        }
+
+        let n = buf.len().min(state.pending.len());
+        for (dst, src) in buf.iter_mut().zip(state.pending.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for ReplayFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_file() {
+        let path = std::env::temp_dir().join(format!("replay-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "1700000000.000 534B52455345540D0A\n1700000000.500 4F4B0D0A\n",
+        )
+        .unwrap();
+
+        let mut device = ReplayFile::open(path).unwrap();
+        let mut reader = device.try_clone_device().unwrap();
+        assert_eq!(device.write(b"SKVER\r\n").unwrap(), 7);
+
+        let mut buf = [0; 4];
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"SKRE");
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"SET\r");
+        assert_eq!(reader.read(&mut buf).unwrap(), 1);
+        assert_eq!(&buf[..1], b"\n");
+        assert_eq!(reader.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"OK\r\n");
+        let e = reader.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
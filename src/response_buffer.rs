@@ -0,0 +1,108 @@
+use bytes::{Buf, BytesMut};
+use log::debug;
+
+use crate::parser::{parser, Response};
+
+const INITIAL_CAPACITY: usize = 1024;
+
+// The bytes read from the module, out of which the responses are parsed.
+// A single read may contain several responses or a part of one, so the rest is kept for the next read.
+// Shared by the reader thread of the exporter and the clients.
+#[derive(Debug)]
+pub struct ResponseBuffer {
+    buf: BytesMut,
+}
+
+impl Default for ResponseBuffer {
+    fn default() -> Self {
+        ResponseBuffer::new()
+    }
+}
+
+impl ResponseBuffer {
+    pub fn new() -> ResponseBuffer {
+        ResponseBuffer {
+            buf: BytesMut::with_capacity(INITIAL_CAPACITY),
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    // the first response in the buffer, Ok(None) if more bytes are needed.
+    // On error the buffer is left as is, and the caller decides whether to resync() or give up.
+    pub fn parse_next(&mut self) -> Result<Option<Response>, nom::Err<nom::error::Error<Vec<u8>>>> {
+        let (consumed, response) = match parser(&self.buf) {
+            Ok((rest, response)) => (self.buf.len() - rest.len(), response),
+            Err(nom::Err::Incomplete(n)) => {
+                debug!("parse incomplete: {:?}", n);
+                return Ok(None);
+            }
+            Err(e) => return Err(e.to_owned()),
+        };
+        self.buf.advance(consumed);
+        Ok(Some(response))
+    }
+
+    // a line broken by noise on the UART should not break the following responses, so skip to the next line.
+    // The whole buffer is dropped if the line has not ended yet.
+    pub fn resync(&mut self) {
+        match self.buf.windows(2).position(|w| w == b"\r\n") {
+            Some(pos) => self.buf.advance(pos + 2),
+            None => self.buf.clear(),
+        }
+    }
+
+    // the first response in the buffer skipping the lines which can not be parsed, None if more bytes are needed
+    pub fn next_response(&mut self) -> Option<Response> {
+        loop {
+            match self.parse_next() {
+                Ok(response) => return response,
+                Err(e) => {
+                    debug!("skip unknown line: {:?}", e);
+                    self.resync();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_response() {
+        let mut buf = ResponseBuffer::new();
+        buf.extend(b"SKRESET\r\nOK\r\nGARBAGE\r\nSKRES");
+        assert_eq!(buf.next_response(), Some(Response::SkReset));
+        // the broken line is skipped, and the rest waits for more bytes
+        assert_eq!(buf.next_response(), None);
+        assert_eq!(buf.as_bytes(), b"SKRES");
+        buf.extend(b"ET\r\nOK\r\n");
+        assert_eq!(buf.next_response(), Some(Response::SkReset));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut buf = ResponseBuffer::new();
+        buf.extend(b"GARBAGE\r\nSKRESET\r\nOK\r\n");
+        assert!(buf.parse_next().is_err());
+        buf.resync();
+        assert_eq!(buf.parse_next().unwrap(), Some(Response::SkReset));
+
+        buf.extend(b"GARBAGE");
+        buf.resync();
+        assert!(buf.is_empty());
+    }
+}
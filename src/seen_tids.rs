@@ -0,0 +1,48 @@
+use std::collections::VecDeque;
+
+// TIDs of the latest responses which are already handled, to tell duplicated frames from late responses.
+// Only the last `capacity` TIDs are kept since TID wraps around.
+#[derive(Debug)]
+pub struct SeenTids {
+    tids: VecDeque<u16>,
+    capacity: usize,
+}
+
+impl SeenTids {
+    pub fn new(capacity: usize) -> SeenTids {
+        SeenTids {
+            tids: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn insert(&mut self, tid: u16) {
+        if self.tids.len() == self.capacity {
+            self.tids.pop_front();
+        }
+        self.tids.push_back(tid);
+    }
+
+    pub fn contains(&self, tid: u16) -> bool {
+        self.tids.contains(&tid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seen_tids() {
+        let mut seen = SeenTids::new(2);
+        seen.insert(1);
+        seen.insert(2);
+        assert!(seen.contains(1));
+        assert!(seen.contains(2));
+
+        seen.insert(3);
+        assert!(!seen.contains(1));
+        assert!(seen.contains(2));
+        assert!(seen.contains(3));
+    }
+}
@@ -0,0 +1,63 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Times of the recent EVENT 0x21 send failures. Many of them within `window` suggest the channel is congested,
+// which resending on the same channel does not solve. A threshold of 0 never reports congestion.
+#[derive(Debug)]
+pub struct SendFailures {
+    times: VecDeque<Instant>,
+    threshold: usize,
+    window: Duration,
+}
+
+impl SendFailures {
+    pub fn new(threshold: usize, window: Duration) -> SendFailures {
+        SendFailures {
+            times: VecDeque::with_capacity(threshold),
+            threshold,
+            window,
+        }
+    }
+
+    // true if this failure reaches the threshold, after which the failures are counted from scratch
+    pub fn record(&mut self, now: Instant) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        while self
+            .times
+            .front()
+            .is_some_and(|t| now.saturating_duration_since(*t) > self.window)
+        {
+            self.times.pop_front();
+        }
+        self.times.push_back(now);
+        if self.times.len() >= self.threshold {
+            self.times.clear();
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_send_failures() {
+        let start = Instant::now();
+        let mut failures = SendFailures::new(3, Duration::from_secs(60));
+        assert!(!failures.record(start));
+        assert!(!failures.record(start + Duration::from_secs(30)));
+        // the earlier ones fall out of the window
+        assert!(!failures.record(start + Duration::from_secs(90)));
+        assert!(!failures.record(start + Duration::from_secs(100)));
+        assert!(failures.record(start + Duration::from_secs(110)));
+        // counted from scratch
+        assert!(!failures.record(start + Duration::from_secs(111)));
+
+        let mut disabled = SendFailures::new(0, Duration::from_secs(60));
+        assert!(!disabled.record(start));
+    }
+}
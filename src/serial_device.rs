@@ -0,0 +1,94 @@
+use std::error::Error;
+use std::fs;
+
+use serialport::SerialPortType;
+
+// USB dongles get a different /dev/ttyUSBn depending on the order they are enumerated, so the module can also be
+// specified by its stable symlink in /dev/serial/by-id or by the USB vendor and product id, which are resolved to
+// the device node each time the port is opened.
+
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+// the device node of the by-id symlink whose name contains `pattern`, or of the path itself if it is one
+pub fn resolve_by_id(pattern: &str, index: Option<usize>) -> Result<String, Box<dyn Error>> {
+    if pattern.contains('/') {
+        return Ok(fs::canonicalize(pattern)?.to_string_lossy().into_owned());
+    }
+    let mut names = Vec::new();
+    for entry in fs::read_dir(BY_ID_DIR)? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if name.contains(pattern) {
+            names.push(name);
+        }
+    }
+    names.sort();
+    let name = select(names, index, pattern)?;
+    Ok(fs::canonicalize(format!("{}/{}", BY_ID_DIR, name))?
+        .to_string_lossy()
+        .into_owned())
+}
+
+// the device node of the USB serial port of "VID:PID" in hex
+pub fn resolve_usb_id(usb_id: &str, index: Option<usize>) -> Result<String, Box<dyn Error>> {
+    let (vid, pid) = parse_usb_id(usb_id)?;
+    let mut ports: Vec<String> = serialport::available_ports()?
+        .into_iter()
+        .filter(|port| matches!(&port.port_type, SerialPortType::UsbPort(usb) if usb.vid == vid && usb.pid == pid))
+        .map(|port| port.port_name)
+        .collect();
+    ports.sort();
+    select(ports, index, usb_id)
+}
+
+pub fn parse_usb_id(raw: &str) -> Result<(u16, u16), Box<dyn Error>> {
+    match raw.split_once(':') {
+        Some((vid, pid)) => Ok((u16::from_str_radix(vid, 16)?, u16::from_str_radix(pid, 16)?)),
+        None => Err(format!("expected VID:PID, got {:?}", raw).into()),
+    }
+}
+
+// the candidate at `index`, which is required only if there are more than one
+fn select(
+    candidates: Vec<String>,
+    index: Option<usize>,
+    what: &str,
+) -> Result<String, Box<dyn Error>> {
+    match (candidates.len(), index) {
+        (0, _) => Err(format!("no serial device matches {:?}", what).into()),
+        (1, None) => Ok(candidates.into_iter().next().unwrap()),
+        (_, None) => Err(format!(
+            "{} serial devices match {:?}, set SERIAL_INDEX to choose one of {:?}",
+            candidates.len(),
+            what,
+            candidates
+        )
+        .into()),
+        (n, Some(i)) if i >= n => {
+            Err(format!("SERIAL_INDEX {} is out of {:?}", i, candidates).into())
+        }
+        (_, Some(i)) => Ok(candidates.into_iter().nth(i).unwrap()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_usb_id() {
+        assert_eq!(parse_usb_id("0403:6015").unwrap(), (0x0403, 0x6015));
+        assert!(parse_usb_id("0403").is_err());
+        assert!(parse_usb_id("0403:xyz").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let one = vec!["/dev/ttyUSB0".to_string()];
+        let two = vec!["/dev/ttyUSB0".to_string(), "/dev/ttyUSB1".to_string()];
+        assert_eq!(select(one.clone(), None, "x").unwrap(), "/dev/ttyUSB0");
+        assert!(select(one, Some(1), "x").is_err());
+        assert!(select(two.clone(), None, "x").is_err());
+        assert_eq!(select(two, Some(1), "x").unwrap(), "/dev/ttyUSB1");
+        assert!(select(Vec::new(), None, "x").is_err());
+    }
+}
@@ -0,0 +1,41 @@
+// Exponential moving average of the readings, where alpha is the weight of the latest reading.
+// alpha = 2 / (N + 1) roughly averages the last N readings.
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialMovingAverage {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl ExponentialMovingAverage {
+    pub fn new(alpha: f64) -> ExponentialMovingAverage {
+        ExponentialMovingAverage { alpha, value: None }
+    }
+
+    // the first reading is taken as is, so that the average does not start from 0
+    pub fn update(&mut self, reading: f64) -> f64 {
+        let value = match self.value {
+            Some(value) => value + self.alpha * (reading - value),
+            None => reading,
+        };
+        self.value = Some(value);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_moving_average() {
+        let mut ema = ExponentialMovingAverage::new(0.5);
+        assert_eq!(ema.update(100.0), 100.0);
+        assert_eq!(ema.update(200.0), 150.0);
+        assert_eq!(ema.update(200.0), 175.0);
+
+        // alpha = 1 follows the readings
+        let mut ema = ExponentialMovingAverage::new(1.0);
+        ema.update(100.0);
+        assert_eq!(ema.update(300.0), 300.0);
+    }
+}